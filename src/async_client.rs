@@ -0,0 +1,332 @@
+use crate::model::{ConnectReason, Message, QoS};
+use crate::sync::lock;
+use crate::{Error, Mosquitto, Result};
+use futures_core::Stream;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::mem::ManuallyDrop;
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::{mpsc, oneshot, watch, Notify};
+use tokio::task::JoinHandle;
+
+/// Interval of mosquitto_loop_misc calls, which handle keep alive and retries.
+const MISC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Mosquitto client driven by the tokio runtime.
+///
+/// The socket is registered with tokio through `AsyncFd` and serviced by a task
+/// spawned on connect, so no network thread is needed. Callbacks of the underlying
+/// client are managed by this wrapper and must not be replaced.
+///
+/// The client does not reconnect by itself. Once the connection drops, operations
+/// in flight fail with `ConnectionLost` and `closed` resolves; call connect again
+/// to reconnect. Dropping the client disconnects and stops the socket task.
+pub struct AsyncMosquitto {
+    client: Arc<Mutex<Mosquitto>>,
+    pending: Arc<Pending>,
+    wake: Arc<Notify>,
+    connected: Arc<watch::Sender<bool>>,
+    messages: Option<mpsc::UnboundedReceiver<Message>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for AsyncMosquitto {
+    fn drop(&mut self) {
+        let task = match lock(&self.task).take() {
+            Some(task) => task,
+            None => return,
+        };
+        if !task.is_finished() {
+            info!("AsyncMosquitto: Disconnect on drop");
+            if let Err(err) = self.client().disconnect() {
+                debug!("AsyncMosquitto: Could not disconnect: {}", err);
+            }
+        }
+        task.abort();
+        self.client().fail_deliveries();
+        self.pending.fail_all();
+    }
+}
+
+/// Stream of messages received from broker.
+pub struct MessageStream {
+    receiver: mpsc::UnboundedReceiver<Message>,
+}
+
+impl Stream for MessageStream {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Operations waiting for an acknowledgement from broker.
+#[derive(Default)]
+struct Pending {
+    connect: Mutex<Option<oneshot::Sender<ConnectReason>>>,
+    subscribes: Mutex<HashMap<i32, oneshot::Sender<Vec<i32>>>>,
+}
+
+impl Pending {
+    fn complete_subscribe(&self, mid: i32, granted_qos: &[i32]) {
+        if let Some(sender) = lock(&self.subscribes).remove(&mid) {
+            let _ = sender.send(granted_qos.to_vec());
+        }
+    }
+
+    /// Fail all waiting operations by dropping their senders.
+    fn fail_all(&self) {
+        lock(&self.connect).take();
+        lock(&self.subscribes).clear();
+    }
+}
+
+/// Socket owned by libmosquitto.
+struct Socket(RawFd);
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Socket {
+    /// Return true unless reading would block.
+    fn has_data(&self) -> bool {
+        // The stream must not close the socket owned by libmosquitto.
+        let stream = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(self.0) });
+        let mut buf = [0u8; 1];
+        match stream.peek(&mut buf) {
+            Ok(_) => true,
+            Err(err) => err.kind() != ErrorKind::WouldBlock,
+        }
+    }
+}
+
+impl AsyncMosquitto {
+    pub fn new(id: &str) -> Result<Self> {
+        let mut mosquitto = Mosquitto::new(id)?;
+        mosquitto.callback_init();
+
+        let pending = Arc::new(Pending::default());
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let connected = Arc::new(watch::channel(false).0);
+        let connect_pending = pending.clone();
+        let connect_connected = connected.clone();
+        mosquitto.set_connect_callback(move |_, reason| {
+            // Runs on the socket task, so it is ordered before the reset on close.
+            connect_connected.send_replace(reason.is_accepted());
+            if let Some(sender) = lock(&connect_pending.connect).take() {
+                let _ = sender.send(reason);
+            }
+        });
+
+        let disconnect_pending = pending.clone();
+        mosquitto.set_disconnect_callback(move |_| disconnect_pending.fail_all());
+
+        let subscribe_pending = pending.clone();
+        mosquitto.set_subscribe_callback(move |mid, granted_qos| {
+            subscribe_pending.complete_subscribe(mid, granted_qos)
+        });
+
+        mosquitto.set_message_callback(move |_, message| {
+            if sender.send(message).is_err() {
+                debug!("Message stream dropped, discarding message");
+            }
+        });
+
+        Ok(AsyncMosquitto {
+            client: Arc::new(Mutex::new(mosquitto)),
+            pending,
+            wake: Arc::new(Notify::new()),
+            connected,
+            messages: Some(receiver),
+            task: Mutex::new(None),
+        })
+    }
+
+    /// Return underlying client, e.g. to setup credentials or TLS before connect.
+    pub fn client(&self) -> MutexGuard<'_, Mosquitto> {
+        lock(&self.client)
+    }
+
+    /// Return stream of received messages. Can only be taken once.
+    pub fn messages(&mut self) -> Option<MessageStream> {
+        self.messages
+            .take()
+            .map(|receiver| MessageStream { receiver })
+    }
+
+    /// Connect to broker and wait for CONNACK.
+    /// Must be called within a tokio runtime.
+    ///
+    /// The TCP connect and TLS handshake complete on the socket task, only the
+    /// host name lookup blocks the caller.
+    pub async fn connect(&self, host: &str, port: i32, keep_alive: i32) -> Result<()> {
+        info!("AsyncMosquitto: Connect to broker");
+        let (sender, receiver) = oneshot::channel();
+        *lock(&self.pending.connect) = Some(sender);
+
+        let socket = {
+            let mut client = self.client();
+            client.connect_async(host, port, keep_alive)?;
+            client.socket()?
+        };
+        let socket = AsyncFd::new(Socket(socket)).map_err(Error::Io)?;
+        let task = tokio::spawn(drive(
+            self.client.clone(),
+            self.pending.clone(),
+            self.wake.clone(),
+            self.connected.clone(),
+            socket,
+        ));
+        // A task still serving an earlier connection lost its socket to connect.
+        if let Some(previous) = lock(&self.task).replace(task) {
+            previous.abort();
+        }
+
+        match receiver.await {
+            Ok(reason) if reason.is_accepted() => Ok(()),
+            Ok(reason) => Err(Error::ConnectionRefused(reason).into()),
+            Err(_) => Err(Error::ConnectionLost.into()),
+        }
+    }
+
+    /// Return true while connected to broker.
+    pub fn is_connected(&self) -> bool {
+        *self.connected.borrow()
+    }
+
+    /// Resolve once the connection was closed, either by disconnect or because it
+    /// dropped.
+    pub async fn closed(&self) {
+        let mut connected = self.connected.subscribe();
+        let _ = connected.wait_for(|connected| !connected).await;
+    }
+
+    /// Disconnect from broker.
+    pub fn disconnect(&self) -> Result<()> {
+        self.client().disconnect()?;
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Publish to broker. Resolves once the publish was sent for QoS 0,
+    /// on PUBACK for QoS 1 and on PUBCOMP for QoS 2. A message put into the
+    /// offline queue resolves once it was flushed and acknowledged.
+    pub async fn publish<T: AsRef<str>>(
+        &self,
+        topic: T,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<()> {
+        let token = self.client().publish_qos(topic, payload, qos, retain)?;
+        self.wake.notify_one();
+
+        token.await
+    }
+
+    /// Subscribe to broker. Resolves on SUBACK with the granted QoS.
+    pub async fn subscribe<T: AsRef<str>>(&self, topic: T, qos: QoS) -> Result<i32> {
+        let receiver = {
+            let client = self.client();
+            let mid = client.subscribe_qos(topic, qos)?;
+            let (sender, receiver) = oneshot::channel();
+            lock(&self.pending.subscribes).insert(mid, sender);
+            receiver
+        };
+        self.wake.notify_one();
+
+        let granted_qos = receiver.await.map_err(|_| Error::ConnectionLost)?;
+        match granted_qos.first() {
+            Some(&granted) if granted < 0x80 => Ok(granted),
+            Some(&granted) => Err(Error::SubscriptionRejected(granted).into()),
+            None => Err(Error::UnexpectedBehaviour.into()),
+        }
+    }
+
+    /// Unsubscribe from broker.
+    pub fn unsubscribe<T: AsRef<str>>(&self, topic: T) -> Result<()> {
+        self.client().unsubscribe(topic)?;
+        self.wake.notify_one();
+        Ok(())
+    }
+}
+
+/// Service the socket until the connection is closed.
+async fn drive(
+    client: Arc<Mutex<Mosquitto>>,
+    pending: Arc<Pending>,
+    wake: Arc<Notify>,
+    connected: Arc<watch::Sender<bool>>,
+    socket: AsyncFd<Socket>,
+) {
+    debug!(
+        "AsyncMosquitto: Start driving socket {}",
+        socket.get_ref().0
+    );
+    let mut misc = tokio::time::interval(MISC_INTERVAL);
+
+    loop {
+        let want_write = lock(&client).loop_want_write();
+        let status = tokio::select! {
+            ready = socket.readable() => match ready {
+                Ok(mut guard) => {
+                    let status = read_available(&client, guard.get_inner());
+                    if status.is_ok() {
+                        guard.clear_ready();
+                    }
+                    status
+                }
+                Err(err) => Err(Error::Io(err).into()),
+            },
+            ready = socket.writable(), if want_write => match ready {
+                Ok(mut guard) => {
+                    let client = lock(&client);
+                    let status = client.loop_write(1);
+                    if client.loop_want_write() {
+                        guard.clear_ready();
+                    }
+                    status
+                }
+                Err(err) => Err(Error::Io(err).into()),
+            },
+            _ = misc.tick() => {
+                // Also read data which TLS may have buffered without socket activity.
+                let client = lock(&client);
+                client.loop_misc().and_then(|_| client.loop_read(1))
+            },
+            _ = wake.notified() => Ok(()),
+        };
+
+        if let Err(err) = status {
+            warn!("AsyncMosquitto: Connection closed: {}", err);
+            break;
+        }
+    }
+
+    connected.send_replace(false);
+    pending.fail_all();
+    // libmosquitto does not call the disconnect callback for every closed socket.
+    lock(&client).fail_deliveries();
+}
+
+/// Read packets until the socket would block.
+fn read_available(client: &Mutex<Mosquitto>, socket: &Socket) -> Result<()> {
+    loop {
+        lock(client).loop_read(1)?;
+        if !socket.has_data() {
+            return Ok(());
+        }
+    }
+}
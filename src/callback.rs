@@ -1,17 +1,85 @@
-use crate::model::Message;
+use crate::client_handle::ClientHandle;
+use crate::delivery::Deliveries;
+use crate::model::{ConnectReason, Message};
+use crate::offline_queue::OfflineQueue;
+use crate::property;
+use crate::router::Router;
+use crate::rpc::Requests;
+use crate::subscription::Subscriptions;
+use crate::sync::lock;
+use crate::Error;
 use log::{debug, error, info};
 use mosquitto_mqtt_sys_rust as mqtt;
 use std::ffi::CStr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 const MAGIC_NUMBER: u32 = 0xCA11_BACC;
 
+type MessageCallback = Box<dyn FnMut(&ClientHandle, Message) + Send>;
+
+type ConnectCallback = Box<dyn FnMut(&ClientHandle, ConnectReason) + Send>;
+
+type SubscribeCallback = Box<dyn FnMut(i32, &[i32]) + Send>;
+
+type LogCallback = Box<dyn FnMut(i32, &str) + Send>;
+
+type ErrorCallback = Box<dyn FnMut(&Error) + Send>;
+
+type IdCallback = Box<dyn FnMut(i32) + Send>;
+
+type KeyPasswordCallback = Box<dyn Fn() -> String + Send>;
+
+/// Slot of a value which may be replaced while the network thread uses it.
+type Slot<T> = Mutex<Option<T>>;
+
+/// Handler for errors raised inside callbacks, which cannot be returned to the
+/// caller. Errors are logged until a handler is set.
+#[derive(Clone, Default)]
+pub(crate) struct ErrorHandler {
+    handler: Arc<Mutex<Option<ErrorCallback>>>,
+}
+
+impl ErrorHandler {
+    pub fn set(&self, handler: ErrorCallback) {
+        *lock(&self.handler) = Some(handler);
+    }
+
+    pub fn report(&self, err: &Error) {
+        match &mut *lock(&self.handler) {
+            Some(handler) => handler(err),
+            None => error!("Error in callback: {}", err),
+        }
+    }
+}
+
+/// State shared with the C callbacks through the user data of libmosquitto.
+///
+/// Callbacks and handlers may be set or cleared while the network thread runs, so
+/// each is kept in a slot behind a mutex. A callback is called with its slot
+/// locked; once a setter or clear returns, the previous callback is not running
+/// and will not be called again. Subscriptions, deliveries, the error handler and
+/// the client handle are only set before the client is used.
 #[derive(Default)]
 pub struct Callbacks {
     magic_number: u32,
-    message_callback: Option<Box<dyn Fn(Message)>>,
-    connect_callback: Option<Box<dyn Fn(i32)>>,
-    disconnect_callback: Option<Box<dyn Fn(i32)>>,
-    init: bool,
+    message_callback: Slot<MessageCallback>,
+    connect_callback: Slot<ConnectCallback>,
+    disconnect_callback: Slot<IdCallback>,
+    publish_callback: Slot<IdCallback>,
+    subscribe_callback: Slot<SubscribeCallback>,
+    unsubscribe_callback: Slot<IdCallback>,
+    log_callback: Slot<LogCallback>,
+    requests: Slot<Arc<Requests>>,
+    router: Slot<Arc<Mutex<Router>>>,
+    subscriptions: Option<Arc<Subscriptions>>,
+    queue: Slot<Arc<OfflineQueue>>,
+    deliveries: Option<Arc<Deliveries>>,
+    errors: ErrorHandler,
+    handle: Option<ClientHandle>,
+    key_password: Slot<KeyPasswordCallback>,
+    init: AtomicBool,
 }
 
 impl Drop for Callbacks {
@@ -25,21 +93,32 @@ impl Callbacks {
     pub fn new() -> Self {
         Callbacks {
             magic_number: MAGIC_NUMBER,
-            message_callback: None,
-            connect_callback: None,
-            disconnect_callback: None,
-            init: false,
+            message_callback: Mutex::new(None),
+            connect_callback: Mutex::new(None),
+            disconnect_callback: Mutex::new(None),
+            publish_callback: Mutex::new(None),
+            subscribe_callback: Mutex::new(None),
+            unsubscribe_callback: Mutex::new(None),
+            log_callback: Mutex::new(None),
+            requests: Mutex::new(None),
+            router: Mutex::new(None),
+            subscriptions: None,
+            queue: Mutex::new(None),
+            deliveries: None,
+            errors: ErrorHandler::default(),
+            handle: None,
+            key_password: Mutex::new(None),
+            init: AtomicBool::new(false),
         }
     }
 
     /// # Safety
     ///
     /// Initialize user_data.
-    pub unsafe fn initialize(&mut self, mosq: *mut mqtt::mosquitto) {
+    pub unsafe fn initialize(&self, mosq: *mut mqtt::mosquitto) {
         info!("Initialize user data for mosquitto");
-        if !self.init {
-            self.init = true;
-            let pdata: *const Callbacks = &*self;
+        if !self.init.swap(true, Ordering::SeqCst) {
+            let pdata: *const Callbacks = self;
             mqtt::mosquitto_user_data_set(mosq, pdata as *mut ::std::os::raw::c_void);
         }
     }
@@ -47,42 +126,288 @@ impl Callbacks {
     /// # Safety
     ///
     /// Sets connect callback.
-    pub unsafe fn on_connect<C>(&mut self, mosq: *mut mqtt::mosquitto, callback: C)
+    pub unsafe fn on_connect<C>(&self, mosq: *mut mqtt::mosquitto, callback: C)
     where
-        C: Fn(i32),
+        C: FnMut(&ClientHandle, ConnectReason) + Send,
         C: 'static,
     {
         info!("Set connect_callback");
         self.initialize(mosq);
-        mqtt::mosquitto_connect_callback_set(mosq, Some(mqtt_connect_callback));
-        self.connect_callback = Some(Box::new(callback))
+        mqtt::mosquitto_connect_with_flags_callback_set(
+            mosq,
+            Some(mqtt_connect_with_flags_callback),
+        );
+        *lock(&self.connect_callback) = Some(Box::new(callback))
     }
 
     /// # Safety
     ///
     /// Sets disconnect callback.
-    pub unsafe fn on_disconnect<C>(&mut self, mosq: *mut mqtt::mosquitto, callback: C)
+    pub unsafe fn on_disconnect<C>(&self, mosq: *mut mqtt::mosquitto, callback: C)
     where
-        C: Fn(i32),
+        C: FnMut(i32) + Send,
         C: 'static,
     {
         info!("Set disconnect_callback");
         self.initialize(mosq);
         mqtt::mosquitto_disconnect_callback_set(mosq, Some(mqtt_disconnect_callback));
-        self.disconnect_callback = Some(Box::new(callback));
+        *lock(&self.disconnect_callback) = Some(Box::new(callback));
+    }
+
+    /// # Safety
+    ///
+    /// Sets publish callback.
+    pub unsafe fn on_publish<C>(&self, mosq: *mut mqtt::mosquitto, callback: C)
+    where
+        C: FnMut(i32) + Send,
+        C: 'static,
+    {
+        info!("Set publish_callback");
+        self.initialize(mosq);
+        mqtt::mosquitto_publish_callback_set(mosq, Some(mqtt_publish_callback));
+        *lock(&self.publish_callback) = Some(Box::new(callback));
+    }
+
+    /// # Safety
+    ///
+    /// Sets subscribe callback.
+    pub unsafe fn on_subscribe<C>(&self, mosq: *mut mqtt::mosquitto, callback: C)
+    where
+        C: FnMut(i32, &[i32]) + Send,
+        C: 'static,
+    {
+        info!("Set subscribe_callback");
+        self.initialize(mosq);
+        mqtt::mosquitto_subscribe_callback_set(mosq, Some(mqtt_subscribe_callback));
+        *lock(&self.subscribe_callback) = Some(Box::new(callback));
+    }
+
+    /// # Safety
+    ///
+    /// Sets unsubscribe callback.
+    pub unsafe fn on_unsubscribe<C>(&self, mosq: *mut mqtt::mosquitto, callback: C)
+    where
+        C: FnMut(i32) + Send,
+        C: 'static,
+    {
+        info!("Set unsubscribe_callback");
+        self.initialize(mosq);
+        mqtt::mosquitto_unsubscribe_callback_set(mosq, Some(mqtt_unsubscribe_callback));
+        *lock(&self.unsubscribe_callback) = Some(Box::new(callback));
+    }
+
+    /// # Safety
+    ///
+    /// Sets log callback.
+    pub unsafe fn on_log<C>(&self, mosq: *mut mqtt::mosquitto, callback: C)
+    where
+        C: FnMut(i32, &str) + Send,
+        C: 'static,
+    {
+        info!("Set log_callback");
+        self.initialize(mosq);
+        mqtt::mosquitto_log_callback_set(mosq, Some(mqtt_log_callback));
+        *lock(&self.log_callback) = Some(Box::new(callback));
     }
 
     /// # Safety
     ///
     /// Sets message_callback.
-    pub unsafe fn on_message<C>(&mut self, mosq: *mut mqtt::mosquitto, callback: C)
+    pub unsafe fn on_message<C>(&self, mosq: *mut mqtt::mosquitto, callback: C)
     where
-        C: Fn(Message),
+        C: FnMut(&ClientHandle, Message) + Send,
         C: 'static,
     {
         self.initialize(mosq);
-        mqtt::mosquitto_message_callback_set(mosq, Some(mqtt_message_callback));
-        self.message_callback = Some(Box::new(callback));
+        mqtt::mosquitto_message_v5_callback_set(mosq, Some(mqtt_message_callback));
+        *lock(&self.message_callback) = Some(Box::new(callback));
+    }
+
+    /// Removes connect callback, waiting for a running call to return.
+    ///
+    /// Unlike the other clear methods, the C callback is not unregistered: it also
+    /// replays subscriptions and flushes the offline queue, which must keep working
+    /// without a user callback. It only skips the empty slot.
+    pub fn clear_connect(&self) {
+        info!("Clear connect_callback");
+        lock(&self.connect_callback).take();
+    }
+
+    /// Removes disconnect callback, waiting for a running call to return.
+    ///
+    /// The C callback is not unregistered, as it also fails delivery tokens and
+    /// tells the offline queue about the lost connection.
+    pub fn clear_disconnect(&self) {
+        info!("Clear disconnect_callback");
+        lock(&self.disconnect_callback).take();
+    }
+
+    /// Removes publish callback, waiting for a running call to return.
+    ///
+    /// The C callback is not unregistered, as it also completes delivery tokens and
+    /// removes acknowledged messages from the offline queue.
+    pub fn clear_publish(&self) {
+        info!("Clear publish_callback");
+        lock(&self.publish_callback).take();
+    }
+
+    /// # Safety
+    ///
+    /// Removes subscribe callback.
+    pub unsafe fn clear_subscribe(&self, mosq: *mut mqtt::mosquitto) {
+        info!("Clear subscribe_callback");
+        mqtt::mosquitto_subscribe_callback_set(mosq, None);
+        lock(&self.subscribe_callback).take();
+    }
+
+    /// # Safety
+    ///
+    /// Removes unsubscribe callback.
+    pub unsafe fn clear_unsubscribe(&self, mosq: *mut mqtt::mosquitto) {
+        info!("Clear unsubscribe_callback");
+        mqtt::mosquitto_unsubscribe_callback_set(mosq, None);
+        lock(&self.unsubscribe_callback).take();
+    }
+
+    /// # Safety
+    ///
+    /// Removes log callback.
+    pub unsafe fn clear_log(&self, mosq: *mut mqtt::mosquitto) {
+        info!("Clear log_callback");
+        mqtt::mosquitto_log_callback_set(mosq, None);
+        lock(&self.log_callback).take();
+    }
+
+    /// # Safety
+    ///
+    /// Removes message callback. The C callback stays registered while requests
+    /// or routes need it.
+    pub unsafe fn clear_message(&self, mosq: *mut mqtt::mosquitto) {
+        info!("Clear message_callback");
+        if lock(&self.requests).is_none() && lock(&self.router).is_none() {
+            mqtt::mosquitto_message_v5_callback_set(mosq, None);
+        }
+        lock(&self.message_callback).take();
+    }
+
+    /// # Safety
+    ///
+    /// Routes replies to outstanding requests.
+    pub(crate) unsafe fn on_reply(&self, mosq: *mut mqtt::mosquitto, requests: Arc<Requests>) {
+        info!("Set request reply handling");
+        self.initialize(mosq);
+        *lock(&self.requests) = Some(requests);
+        mqtt::mosquitto_message_v5_callback_set(mosq, Some(mqtt_message_callback));
+    }
+
+    /// # Safety
+    ///
+    /// Dispatches messages through router before the message callback.
+    pub(crate) unsafe fn on_route(&self, mosq: *mut mqtt::mosquitto, router: Arc<Mutex<Router>>) {
+        info!("Set message router");
+        self.initialize(mosq);
+        *lock(&self.router) = Some(router);
+        mqtt::mosquitto_message_v5_callback_set(mosq, Some(mqtt_message_callback));
+    }
+
+    /// # Safety
+    ///
+    /// Resubscribes when client connects without a session present on broker.
+    pub(crate) unsafe fn on_session(
+        &mut self,
+        mosq: *mut mqtt::mosquitto,
+        subscriptions: Arc<Subscriptions>,
+    ) {
+        info!("Set subscription replay");
+        self.initialize(mosq);
+        mqtt::mosquitto_connect_with_flags_callback_set(
+            mosq,
+            Some(mqtt_connect_with_flags_callback),
+        );
+        self.subscriptions = Some(subscriptions);
+    }
+
+    /// # Safety
+    ///
+    /// Tracks connection state for the offline queue and removes acknowledged
+    /// messages from it. Queued messages are flushed on connect.
+    pub(crate) unsafe fn on_queue(&self, mosq: *mut mqtt::mosquitto, queue: Arc<OfflineQueue>) {
+        info!("Set offline queue");
+        self.initialize(mosq);
+        *lock(&self.queue) = Some(queue);
+        mqtt::mosquitto_connect_with_flags_callback_set(
+            mosq,
+            Some(mqtt_connect_with_flags_callback),
+        );
+        mqtt::mosquitto_disconnect_callback_set(mosq, Some(mqtt_disconnect_callback));
+        mqtt::mosquitto_publish_callback_set(mosq, Some(mqtt_publish_callback));
+    }
+
+    /// # Safety
+    ///
+    /// Completes delivery tokens on publish and fails them on disconnect.
+    pub(crate) unsafe fn on_delivery(
+        &mut self,
+        mosq: *mut mqtt::mosquitto,
+        deliveries: Arc<Deliveries>,
+    ) {
+        info!("Set delivery tracking");
+        self.initialize(mosq);
+        mqtt::mosquitto_disconnect_callback_set(mosq, Some(mqtt_disconnect_callback));
+        mqtt::mosquitto_publish_callback_set(mosq, Some(mqtt_publish_callback));
+        self.deliveries = Some(deliveries);
+    }
+
+    /// Lend handle to message and connect callbacks. Must be set before the
+    /// client is used.
+    pub(crate) fn on_handle(&mut self, handle: ClientHandle) {
+        self.handle = Some(handle);
+    }
+
+    /// Report errors raised in callbacks to handler. Must be set before the client
+    /// is used.
+    pub(crate) fn on_error(&mut self, errors: ErrorHandler) {
+        self.errors = errors;
+    }
+
+    /// # Safety
+    ///
+    /// Sets password callback for encrypted TLS keys.
+    /// mqtt_pw_callback must be passed to mosquitto_tls_set.
+    pub(crate) unsafe fn on_key_password(
+        &self,
+        mosq: *mut mqtt::mosquitto,
+        callback: KeyPasswordCallback,
+    ) {
+        info!("Set key_password callback");
+        self.initialize(mosq);
+        *lock(&self.key_password) = Some(callback);
+    }
+}
+
+/// Run callback code, catching panics so they never unwind into libmosquitto.
+/// A caught panic is reported to the error handler and default is returned.
+fn guard<R, F>(errors: &ErrorHandler, callback: &str, default: R, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => match payload.downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => "unknown panic".to_string(),
+                },
+            };
+            let err = Error::CallbackPanic(callback.to_string(), message);
+            let reported = panic::catch_unwind(AssertUnwindSafe(|| errors.report(&err)));
+            if reported.is_err() {
+                error!("Error callback panicked while reporting: {}", err);
+            }
+            default
+        }
     }
 }
 
@@ -94,53 +419,129 @@ extern "C" fn mqtt_message_callback(
     _mosq: *mut mqtt::mosquitto,
     data: *mut ::std::os::raw::c_void,
     msg: *const mqtt::mosquitto_message,
+    props: *const mqtt::mosquitto_property,
 ) {
     debug!("Recieved MQTT_Message_Callback");
     if msg.is_null() {
         return;
     }
 
-    let this = unsafe { &mut *(data as *mut Callbacks) };
+    let this = unsafe { &*(data as *const Callbacks) };
 
     if this.magic_number != MAGIC_NUMBER {
         error!("Magic number is not valid for disconnect_callback");
         return;
     }
 
-    if let Some(ref callback) = this.message_callback {
+    let errors = this.errors.clone();
+    guard(&errors, "message_callback", (), || {
         let mqtt_msg = unsafe { &mut *(msg as *mut mqtt::mosquitto_message) };
-        let payload = unsafe {
-            CStr::from_ptr(mqtt_msg.payload as *const _)
-                .to_str()
-                .unwrap()
+        let topic = unsafe { CStr::from_ptr(mqtt_msg.topic).to_string_lossy() };
+        let payload = if mqtt_msg.payload.is_null() || mqtt_msg.payloadlen <= 0 {
+            &[]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(
+                    mqtt_msg.payload as *const u8,
+                    mqtt_msg.payloadlen as usize,
+                )
+            }
         };
-        let topic = unsafe { CStr::from_ptr(mqtt_msg.topic).to_str().unwrap() };
-        let message = Message::new(topic, payload);
-        callback(message);
-    }
+        let mut message = Message::new(&topic, payload);
+        unsafe {
+            message = message
+                .with_content_type(property::read_string(
+                    props,
+                    mqtt::mqtt5_property_MQTT_PROP_CONTENT_TYPE,
+                ))
+                .with_response_topic(property::read_string(
+                    props,
+                    mqtt::mqtt5_property_MQTT_PROP_RESPONSE_TOPIC,
+                ))
+                .with_correlation_data(property::read_binary(
+                    props,
+                    mqtt::mqtt5_property_MQTT_PROP_CORRELATION_DATA,
+                ));
+        }
+
+        let requests = lock(&this.requests).clone();
+        if let Some(requests) = requests {
+            message = match requests.complete(message) {
+                Some(message) => message,
+                None => return,
+            };
+        }
+
+        let handle = match this.handle {
+            Some(ref handle) => handle,
+            None => return,
+        };
+
+        let router = lock(&this.router).clone();
+        if let Some(router) = router {
+            if lock(&router).dispatch(handle, &message) {
+                return;
+            }
+        }
+
+        if let Some(callback) = lock(&this.message_callback).as_mut() {
+            callback(handle, message);
+        }
+    })
 }
 
 /// # Safety
 ///
-/// C-like mqtt_connect_callback.
-/// Will be called when client is connected to broker.
-extern "C" fn mqtt_connect_callback(
-    _mosq: *mut mqtt::mosquitto,
+/// C-like mqtt_connect_with_flags_callback.
+/// Will be called when client is connected to broker; flags tell whether broker
+/// kept the session of the client.
+extern "C" fn mqtt_connect_with_flags_callback(
+    mosq: *mut mqtt::mosquitto,
     data: *mut ::std::os::raw::c_void,
     response: ::std::os::raw::c_int,
+    flags: ::std::os::raw::c_int,
 ) {
-    debug!("Recieved MQTT_Connect_Callback");
-    let this = unsafe { &mut *(data as *mut Callbacks) };
+    debug!("Recieved MQTT_Connect_With_Flags_Callback");
+    let this = unsafe { &*(data as *const Callbacks) };
 
     if this.magic_number != MAGIC_NUMBER {
-        error!("Magic number is not valid for disconnect_callback");
+        error!("Magic number is not valid for connect_with_flags_callback");
         return;
     }
 
-    if let Some(ref callback) = this.connect_callback {
-        debug!("Notify connect_callback");
-        callback(response)
-    }
+    let errors = this.errors.clone();
+    guard(&errors, "connect_with_flags_callback", (), || {
+        let accepted = response == 0;
+        if accepted {
+            if let Some(ref subscriptions) = this.subscriptions {
+                subscriptions.connected();
+            }
+        }
+
+        // Filters subscribed by the connect callback are skipped by the replay.
+        if let (Some(callback), Some(handle)) =
+            (lock(&this.connect_callback).as_mut(), &this.handle)
+        {
+            debug!("Notify connect_callback");
+            callback(handle, ConnectReason::from_code(response))
+        }
+
+        if !accepted {
+            return;
+        }
+
+        let session_present = flags & 0x01 != 0;
+        if !session_present {
+            if let Some(ref subscriptions) = this.subscriptions {
+                unsafe { subscriptions.replay(mosq) }
+            }
+        }
+
+        let queue = lock(&this.queue).clone();
+        if let Some(queue) = queue {
+            unsafe { queue.flush(mosq) }
+        }
+    })
 }
 
 /// # Safety
@@ -153,15 +554,197 @@ extern "C" fn mqtt_disconnect_callback(
     response: ::std::os::raw::c_int,
 ) {
     debug!("Recieved MQTT_Disconnect_Callback");
-    let this = unsafe { &mut *(data as *mut Callbacks) };
+    let this = unsafe { &*(data as *const Callbacks) };
 
     if this.magic_number != MAGIC_NUMBER {
         error!("Magic number is not valid for disconnect_callback");
         return;
     }
 
-    if let Some(ref callback) = this.disconnect_callback {
-        debug!("Notify disconnect_callback");
-        callback(response)
+    let errors = this.errors.clone();
+    guard(&errors, "disconnect_callback", (), || {
+        let queue = lock(&this.queue).clone();
+        if let Some(queue) = queue {
+            queue.disconnected();
+        }
+
+        if let Some(ref deliveries) = this.deliveries {
+            deliveries.fail_all();
+        }
+
+        if let Some(callback) = lock(&this.disconnect_callback).as_mut() {
+            debug!("Notify disconnect_callback");
+            callback(response)
+        }
+    })
+}
+
+/// # Safety
+///
+/// C-like mqtt_publish_callback.
+/// Will be called when a publish has been sent, or acknowledged for QoS > 0.
+extern "C" fn mqtt_publish_callback(
+    _mosq: *mut mqtt::mosquitto,
+    data: *mut ::std::os::raw::c_void,
+    mid: ::std::os::raw::c_int,
+) {
+    debug!("Recieved MQTT_Publish_Callback");
+    let this = unsafe { &*(data as *const Callbacks) };
+
+    if this.magic_number != MAGIC_NUMBER {
+        error!("Magic number is not valid for publish_callback");
+        return;
+    }
+
+    let errors = this.errors.clone();
+    guard(&errors, "publish_callback", (), || {
+        let queue = lock(&this.queue).clone();
+        let queued = match queue {
+            Some(queue) => queue.acknowledge(mid),
+            None => false,
+        };
+
+        if !queued {
+            if let Some(ref deliveries) = this.deliveries {
+                deliveries.complete(mid);
+            }
+        }
+
+        if let Some(callback) = lock(&this.publish_callback).as_mut() {
+            debug!("Notify publish_callback");
+            callback(mid)
+        }
+    })
+}
+
+/// # Safety
+///
+/// C-like mqtt_subscribe_callback.
+/// Will be called when broker acknowledges a subscription.
+extern "C" fn mqtt_subscribe_callback(
+    _mosq: *mut mqtt::mosquitto,
+    data: *mut ::std::os::raw::c_void,
+    mid: ::std::os::raw::c_int,
+    qos_count: ::std::os::raw::c_int,
+    granted_qos: *const ::std::os::raw::c_int,
+) {
+    debug!("Recieved MQTT_Subscribe_Callback");
+    let this = unsafe { &*(data as *const Callbacks) };
+
+    if this.magic_number != MAGIC_NUMBER {
+        error!("Magic number is not valid for subscribe_callback");
+        return;
+    }
+
+    let errors = this.errors.clone();
+    guard(&errors, "subscribe_callback", (), || {
+        if let Some(callback) = lock(&this.subscribe_callback).as_mut() {
+            debug!("Notify subscribe_callback");
+            let granted_qos = if granted_qos.is_null() || qos_count <= 0 {
+                &[]
+            } else {
+                unsafe { std::slice::from_raw_parts(granted_qos, qos_count as usize) }
+            };
+            callback(mid, granted_qos)
+        }
+    })
+}
+
+/// # Safety
+///
+/// C-like mqtt_unsubscribe_callback.
+/// Will be called when broker acknowledges an unsubscription.
+extern "C" fn mqtt_unsubscribe_callback(
+    _mosq: *mut mqtt::mosquitto,
+    data: *mut ::std::os::raw::c_void,
+    mid: ::std::os::raw::c_int,
+) {
+    debug!("Recieved MQTT_Unsubscribe_Callback");
+    let this = unsafe { &*(data as *const Callbacks) };
+
+    if this.magic_number != MAGIC_NUMBER {
+        error!("Magic number is not valid for unsubscribe_callback");
+        return;
     }
+
+    let errors = this.errors.clone();
+    guard(&errors, "unsubscribe_callback", (), || {
+        if let Some(callback) = lock(&this.unsubscribe_callback).as_mut() {
+            debug!("Notify unsubscribe_callback");
+            callback(mid)
+        }
+    })
+}
+
+/// # Safety
+///
+/// C-like mqtt_log_callback.
+/// Will be called for log messages of libmosquitto.
+extern "C" fn mqtt_log_callback(
+    _mosq: *mut mqtt::mosquitto,
+    data: *mut ::std::os::raw::c_void,
+    level: ::std::os::raw::c_int,
+    text: *const ::std::os::raw::c_char,
+) {
+    if text.is_null() {
+        return;
+    }
+
+    let this = unsafe { &*(data as *const Callbacks) };
+
+    if this.magic_number != MAGIC_NUMBER {
+        error!("Magic number is not valid for log_callback");
+        return;
+    }
+
+    let errors = this.errors.clone();
+    guard(&errors, "log_callback", (), || {
+        if let Some(callback) = lock(&this.log_callback).as_mut() {
+            let text = unsafe { CStr::from_ptr(text).to_string_lossy() };
+            callback(level, &text)
+        }
+    })
+}
+
+/// # Safety
+///
+/// C-like mqtt_pw_callback.
+/// Will be called by OpenSSL when an encrypted private key is loaded.
+/// libmosquitto passes the mosquitto instance as userdata.
+pub(crate) extern "C" fn mqtt_pw_callback(
+    buf: *mut ::std::os::raw::c_char,
+    size: ::std::os::raw::c_int,
+    _rwflag: ::std::os::raw::c_int,
+    userdata: *mut ::std::os::raw::c_void,
+) -> ::std::os::raw::c_int {
+    debug!("Recieved MQTT_Pw_Callback");
+    if buf.is_null() || userdata.is_null() || size <= 0 {
+        return 0;
+    }
+
+    let data = unsafe { mqtt::mosquitto_userdata(userdata as *mut mqtt::mosquitto) };
+    if data.is_null() {
+        return 0;
+    }
+    let this = unsafe { &*(data as *const Callbacks) };
+
+    if this.magic_number != MAGIC_NUMBER {
+        error!("Magic number is not valid for pw_callback");
+        return 0;
+    }
+
+    let errors = this.errors.clone();
+    guard(&errors, "pw_callback", 0, || {
+        match lock(&this.key_password).as_ref() {
+            Some(callback) => {
+                let password = callback();
+                let len = password.len().min(size as usize);
+                unsafe {
+                    std::ptr::copy_nonoverlapping(password.as_ptr(), buf as *mut u8, len);
+                }
+                len as ::std::os::raw::c_int
+            }
+            None => 0,
+        }
+    })
 }
@@ -0,0 +1,39 @@
+use crate::model::Message;
+use log::{debug, warn};
+use std::sync::mpsc::{SyncSender, TrySendError};
+
+/// What to do with an incoming message when the message channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the consumer makes room. Stalls the network loop meanwhile,
+    /// including keep alive handling.
+    Block,
+    /// Discard the incoming message.
+    DropNewest,
+}
+
+/// Return message callback forwarding messages into the channel.
+pub(crate) fn forward_messages(
+    sender: SyncSender<Message>,
+    policy: OverflowPolicy,
+) -> impl Fn(Message) {
+    move |message| match policy {
+        OverflowPolicy::Block => {
+            if sender.send(message).is_err() {
+                debug!("Message receiver dropped, discarding message");
+            }
+        }
+        OverflowPolicy::DropNewest => match sender.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(message)) => {
+                warn!(
+                    "Message channel full, dropping message on {}",
+                    message.topic()
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                debug!("Message receiver dropped, discarding message");
+            }
+        },
+    }
+}
@@ -0,0 +1,137 @@
+use crate::delivery::DeliveryToken;
+use crate::model::{Codec, Message, QoS, SubscribeOptions};
+use crate::{Error, Mosquitto, Result};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// Cloneable client handle to share a connected client between threads.
+///
+/// Setup the client with `Mosquitto` first, i.e. callbacks, credentials, TLS and
+/// connect, then convert it. The network thread is started on conversion unless
+/// mqtt_loop_start was already called, as publish and subscribe rely on the
+/// internal locking of libmosquitto, which is only enabled with the threaded
+/// network loop. Reconnects are left to the network thread. A client whose loop
+/// is run by the application, i.e. with set_threaded(true), is rejected with
+/// `Error::ExternalLoop`, as a second loop would race with it.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Shared>,
+}
+
+struct Shared(Mosquitto);
+
+// Only operations guarded by libmosquitto itself or by locks of this crate are
+// exposed; callbacks are Send and run on the network thread.
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+impl TryFrom<Mosquitto> for Client {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(mosquitto: Mosquitto) -> Result<Self> {
+        Client::new(mosquitto)
+    }
+}
+
+impl Client {
+    /// Share client between threads, starting the network thread unless it runs.
+    /// Fails for a client set up with set_threaded(true).
+    pub fn new(mosquitto: Mosquitto) -> Result<Self> {
+        if mosquitto.is_threaded() {
+            return Err(Error::ExternalLoop.into());
+        }
+        if !mosquitto.is_loop_running() {
+            mosquitto.mqtt_loop_start()?;
+        }
+        Ok(Client {
+            inner: Arc::new(Shared(mosquitto)),
+        })
+    }
+
+    /// Publish to broker. Return token tracking delivery of the publish.
+    pub fn publish<T: AsRef<str>>(&self, topic: T, payload: &[u8]) -> Result<DeliveryToken> {
+        self.inner.0.publish(topic, payload)
+    }
+
+    /// Publish to broker with given QoS. Return token tracking delivery of the
+    /// publish.
+    pub fn publish_qos<T: AsRef<str>>(
+        &self,
+        topic: T,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<DeliveryToken> {
+        self.inner.0.publish_qos(topic, payload, qos, retain)
+    }
+
+    /// Publish value encoded by codec. Return token tracking delivery of the publish.
+    pub fn publish_encoded<T, P>(
+        &self,
+        topic: T,
+        value: &P,
+        codec: &dyn Codec<P>,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<DeliveryToken>
+    where
+        T: AsRef<str>,
+    {
+        self.inner
+            .0
+            .publish_encoded(topic, value, codec, qos, retain)
+    }
+
+    /// Publish value serialized as JSON. Return token tracking delivery of the publish.
+    #[cfg(feature = "serde")]
+    pub fn publish_json<T, P>(
+        &self,
+        topic: T,
+        value: &P,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<DeliveryToken>
+    where
+        T: AsRef<str>,
+        P: serde::Serialize,
+    {
+        self.inner.0.publish_json(topic, value, qos, retain)
+    }
+
+    /// Reply to a request with given QoS using its response topic and correlation
+    /// data. Return token tracking delivery of the reply.
+    pub fn respond(&self, request: &Message, payload: &[u8], qos: QoS) -> Result<DeliveryToken> {
+        self.inner.0.respond(request, payload, qos)
+    }
+
+    /// Subscribe to broker with given QoS. Return message id of the subscription.
+    pub fn subscribe<T: AsRef<str>>(&self, topic: T, qos: QoS) -> Result<i32> {
+        self.inner.0.subscribe_qos(topic, qos)
+    }
+
+    /// Subscribe to broker with MQTT v5 subscription options. Return message id of
+    /// the subscription.
+    pub fn subscribe_with_options<T: AsRef<str>>(
+        &self,
+        topic: T,
+        qos: QoS,
+        options: SubscribeOptions,
+    ) -> Result<i32> {
+        self.inner.0.subscribe_with_options(topic, qos, options)
+    }
+
+    /// Unsubscribe from broker.
+    pub fn unsubscribe<T: AsRef<str>>(&self, topic: T) -> Result<()> {
+        self.inner.0.unsubscribe(topic)
+    }
+
+    /// Return number of messages waiting in the offline queue.
+    pub fn queued_messages(&self) -> usize {
+        self.inner.0.queued_messages()
+    }
+
+    /// Disconnect from broker.
+    pub fn disconnect(&self) -> Result<()> {
+        self.inner.0.disconnect()
+    }
+}
@@ -0,0 +1,147 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+
+use crate::delivery::{Deliveries, DeliveryToken};
+use crate::model::{Message, QoS, SubscribeOptions};
+use crate::offline_queue::SharedQueue;
+use crate::property::Properties;
+use crate::rpc;
+use crate::subscription::{self, Subscriptions};
+use crate::sync::lock;
+use crate::{Error, Result};
+use log::debug;
+use std::ffi::CString;
+use std::sync::Arc;
+
+/// Access to the client from within callbacks, e.g. to reply to a message.
+///
+/// A handle is only lent to callbacks and cannot be kept beyond the call.
+pub struct ClientHandle {
+    mosq: *mut mqtt::mosquitto,
+    deliveries: Arc<Deliveries>,
+    subscriptions: Arc<Subscriptions>,
+    queue: SharedQueue,
+}
+
+impl ClientHandle {
+    pub(crate) fn new(
+        mosq: *mut mqtt::mosquitto,
+        deliveries: Arc<Deliveries>,
+        subscriptions: Arc<Subscriptions>,
+        queue: SharedQueue,
+    ) -> Self {
+        ClientHandle {
+            mosq,
+            deliveries,
+            subscriptions,
+            queue,
+        }
+    }
+
+    /// Publish to broker with given QoS. Return token tracking delivery of the
+    /// publish. While disconnected the message goes to the offline queue if one
+    /// is set.
+    pub fn publish<T: AsRef<str>>(
+        &self,
+        topic: T,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<DeliveryToken> {
+        self.send_publish(topic.as_ref(), payload, qos, retain, &Properties::new())
+    }
+
+    /// Publish, or put message into the offline queue while disconnected. A QoS 1
+    /// or 2 message libmosquitto took over before noticing the lost connection is
+    /// tracked by its mid instead. Properties are not kept for queued messages.
+    pub(crate) fn send_publish(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        properties: &Properties,
+    ) -> Result<DeliveryToken> {
+        debug!("ClientHandle: Send publish");
+        let queue = lock(&self.queue).clone();
+        if let Some(queue) = &queue {
+            if let Some(token) = queue.enqueue(topic, payload, qos, retain)? {
+                return Ok(token);
+            }
+        }
+
+        let tpc = CString::new(topic)?;
+        let mut mid: ::std::os::raw::c_int = 0;
+        self.deliveries.begin();
+        let status = unsafe {
+            mqtt::mosquitto_publish_v5(
+                self.mosq,
+                &mut mid,
+                tpc.as_ptr(),
+                payload.len() as ::std::os::raw::c_int,
+                payload.as_ptr() as *const ::std::os::raw::c_void,
+                qos.as_raw(),
+                retain,
+                properties.as_ptr(),
+            )
+        };
+
+        // Without connection libmosquitto still keeps QoS 1 and 2 messages and sends
+        // them after reconnect, so only QoS 0 messages go to the offline queue.
+        let no_conn = status == mqtt::mosq_err_t_MOSQ_ERR_NO_CONN;
+        if status == mqtt::mosq_err_t_MOSQ_ERR_SUCCESS || (no_conn && qos != QoS::AtMostOnce) {
+            if no_conn {
+                if let Some(queue) = &queue {
+                    queue.disconnected();
+                }
+            }
+            return Ok(self.deliveries.track(mid));
+        }
+
+        self.deliveries.abort();
+        if no_conn {
+            if let Some(queue) = &queue {
+                queue.disconnected();
+                if let Some(token) = queue.enqueue(topic, payload, qos, retain)? {
+                    return Ok(token);
+                }
+            }
+        }
+        Err(Error::MosquittoPublish(status).into())
+    }
+
+    /// Reply to a request with given QoS using its response topic and correlation
+    /// data. Return token tracking delivery of the reply.
+    pub fn respond(&self, request: &Message, payload: &[u8], qos: QoS) -> Result<DeliveryToken> {
+        debug!("ClientHandle: Send response");
+        let response_topic = request
+            .response_topic()
+            .ok_or(Error::MissingResponseTopic)?;
+        unsafe {
+            rpc::publish_with_response(
+                self.mosq,
+                &self.deliveries,
+                response_topic,
+                payload,
+                qos,
+                None,
+                request.correlation_data(),
+            )
+        }
+    }
+
+    /// Subscribe to broker with given QoS. Return message id of the subscription.
+    pub fn subscribe<T: AsRef<str>>(&self, topic: T, qos: QoS) -> Result<i32> {
+        debug!("ClientHandle: Subscribe to broker");
+        let options = SubscribeOptions::default();
+        let mid = unsafe { subscription::subscribe(self.mosq, topic.as_ref(), qos, options)? };
+        self.subscriptions.add(topic.as_ref(), qos, options);
+        Ok(mid)
+    }
+
+    /// Unsubscribe from broker.
+    pub fn unsubscribe<T: AsRef<str>>(&self, topic: T) -> Result<()> {
+        debug!("ClientHandle: Unsubscribe from broker");
+        self.subscriptions.remove(topic.as_ref());
+        unsafe { subscription::unsubscribe(self.mosq, topic.as_ref()) }
+    }
+}
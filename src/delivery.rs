@@ -0,0 +1,322 @@
+use crate::sync::lock;
+use crate::{Error, Result};
+use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Pending,
+    Delivered,
+    Lost,
+    Dropped,
+}
+
+impl State {
+    fn result(self) -> Option<Result<()>> {
+        match self {
+            State::Pending => None,
+            State::Delivered => Some(Ok(())),
+            State::Lost => Some(Err(Error::ConnectionLost.into())),
+            State::Dropped => Some(Err(Error::MessageDropped.into())),
+        }
+    }
+}
+
+/// Tracks delivery of a publish.
+///
+/// Completes once the publish was sent for QoS 0, on PUBACK for QoS 1 and on
+/// PUBCOMP for QoS 2. Fails if the connection drops before that. A message put
+/// into the offline queue completes once it was flushed and acknowledged after
+/// reconnect, and fails if the queue drops it.
+///
+/// Besides blocking with wait, the token can be awaited.
+#[derive(Clone)]
+pub struct DeliveryToken {
+    mid: i32,
+    state: Arc<(Mutex<Progress>, Condvar)>,
+}
+
+struct Progress {
+    state: State,
+    // Tasks polling the token, woken on completion.
+    wakers: Vec<Waker>,
+}
+
+impl std::fmt::Debug for DeliveryToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DeliveryToken")
+            .field("mid", &self.mid)
+            .field("state", &self.lock().state)
+            .finish()
+    }
+}
+
+impl DeliveryToken {
+    pub(crate) fn new(mid: i32) -> Self {
+        DeliveryToken {
+            mid,
+            state: Arc::new((
+                Mutex::new(Progress {
+                    state: State::Pending,
+                    wakers: Vec::new(),
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Progress> {
+        lock(&self.state.0)
+    }
+
+    fn complete(&self, state: State) {
+        let mut progress = self.lock();
+        if progress.state == State::Pending {
+            progress.state = state;
+            self.state.1.notify_all();
+            for waker in progress.wakers.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+
+    pub(crate) fn delivered(&self) {
+        self.complete(State::Delivered);
+    }
+
+    pub(crate) fn lost(&self) {
+        self.complete(State::Lost);
+    }
+
+    pub(crate) fn dropped(&self) {
+        self.complete(State::Dropped);
+    }
+
+    /// Return message id of the publish, 0 for a message put into the offline queue.
+    pub fn mid(&self) -> i32 {
+        self.mid
+    }
+
+    /// Return true once the publish was delivered or failed.
+    pub fn is_complete(&self) -> bool {
+        self.lock().state != State::Pending
+    }
+
+    /// Return the outcome without blocking, or None while still in flight.
+    pub fn try_result(&self) -> Option<Result<()>> {
+        self.lock().state.result()
+    }
+
+    /// Block until the publish was delivered or failed.
+    pub fn wait(&self) -> Result<()> {
+        let mut progress = self.lock();
+        loop {
+            if let Some(result) = progress.state.result() {
+                return result;
+            }
+            progress = self
+                .state
+                .1
+                .wait(progress)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Block until the publish was delivered or failed, at most for timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut progress = self.lock();
+        loop {
+            if let Some(result) = progress.state.result() {
+                return result;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::DeliveryTimeout.into());
+            }
+            progress = self
+                .state
+                .1
+                .wait_timeout(progress, remaining)
+                .map(|(progress, _)| progress)
+                .unwrap_or_else(|poisoned| poisoned.into_inner().0);
+        }
+    }
+}
+
+impl Future for DeliveryToken {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut progress = self.lock();
+        if let Some(result) = progress.state.result() {
+            return Poll::Ready(result);
+        }
+        if !progress
+            .wakers
+            .iter()
+            .any(|waker| waker.will_wake(cx.waker()))
+        {
+            progress.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Publishes waiting for the publish callback.
+#[derive(Default)]
+pub(crate) struct Deliveries {
+    tracked: Mutex<Tracked>,
+}
+
+#[derive(Default)]
+struct Tracked {
+    waiting: HashMap<i32, DeliveryToken>,
+    // QoS 0 publishes may complete before mosquitto_publish returns. Only kept
+    // while a publish is being handed over, so untracked mids do not pile up.
+    done: HashSet<i32>,
+    publishing: usize,
+}
+
+impl Deliveries {
+    /// Announce a publish about to be handed to libmosquitto. Must be followed by
+    /// track or abort.
+    pub fn begin(&self) {
+        lock(&self.tracked).publishing += 1;
+    }
+
+    /// Return token of a publish which was just handed to libmosquitto.
+    pub fn track(&self, mid: i32) -> DeliveryToken {
+        let token = DeliveryToken::new(mid);
+        let mut tracked = lock(&self.tracked);
+        if tracked.done.remove(&mid) {
+            token.delivered();
+        } else {
+            tracked.waiting.insert(mid, token.clone());
+        }
+        tracked.finish();
+        token
+    }
+
+    /// Forget a publish which libmosquitto did not accept.
+    pub fn abort(&self) {
+        lock(&self.tracked).finish();
+    }
+
+    pub fn complete(&self, mid: i32) {
+        let mut tracked = lock(&self.tracked);
+        match tracked.waiting.remove(&mid) {
+            Some(token) => token.delivered(),
+            None => {
+                if tracked.publishing > 0 {
+                    tracked.done.insert(mid);
+                }
+            }
+        }
+    }
+
+    /// Fail all publishes still in flight.
+    pub fn fail_all(&self) {
+        let mut tracked = lock(&self.tracked);
+        if !tracked.waiting.is_empty() {
+            debug!("Failing {} publishes in flight", tracked.waiting.len());
+        }
+        for (_, token) in tracked.waiting.drain() {
+            token.lost();
+        }
+        tracked.done.clear();
+    }
+}
+
+impl Tracked {
+    fn finish(&mut self) {
+        self.publishing = self.publishing.saturating_sub(1);
+        if self.publishing == 0 {
+            self.done.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_tracked_publish() {
+        let deliveries = Deliveries::default();
+        deliveries.begin();
+        let token = deliveries.track(1);
+        assert!(token.try_result().is_none());
+        deliveries.complete(1);
+        assert!(token.try_result().unwrap().is_ok());
+    }
+
+    #[test]
+    fn completes_publish_acknowledged_before_track() {
+        let deliveries = Deliveries::default();
+        deliveries.begin();
+        deliveries.complete(1);
+        let token = deliveries.track(1);
+        assert!(token.is_complete());
+        assert!(lock(&deliveries.tracked).done.is_empty());
+    }
+
+    #[test]
+    fn ignores_untracked_mid_outside_publish() {
+        let deliveries = Deliveries::default();
+        deliveries.complete(1);
+        assert!(lock(&deliveries.tracked).done.is_empty());
+
+        deliveries.begin();
+        let token = deliveries.track(1);
+        assert!(!token.is_complete());
+    }
+
+    #[test]
+    fn forgets_untracked_mids_once_publishes_are_handed_over() {
+        let deliveries = Deliveries::default();
+        deliveries.begin();
+        deliveries.complete(7);
+        deliveries.abort();
+        assert!(lock(&deliveries.tracked).done.is_empty());
+        assert_eq!(lock(&deliveries.tracked).publishing, 0);
+    }
+
+    #[test]
+    fn wakes_task_awaiting_token() {
+        struct Flag(std::sync::atomic::AtomicBool);
+
+        impl std::task::Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+
+        let flag = Arc::new(Flag(Default::default()));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut token = DeliveryToken::new(1);
+
+        assert!(Pin::new(&mut token).poll(&mut cx).is_pending());
+        token.clone().delivered();
+        assert!(flag.0.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(matches!(
+            Pin::new(&mut token).poll(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+    }
+
+    #[test]
+    fn fails_publishes_in_flight() {
+        let deliveries = Deliveries::default();
+        deliveries.begin();
+        let token = deliveries.track(1);
+        deliveries.fail_all();
+        assert!(token.wait_timeout(Duration::from_millis(10)).is_err());
+    }
+}
@@ -43,6 +43,12 @@ pub enum Error {
     /// MosquittoMqttLoopStart error.
     MosquittoMqttLoopStart(i32),
 
+    /// MosquittoMqttLoopStop error.
+    MosquittoMqttLoopStop(i32),
+
+    /// MosquittoLoopRead error.
+    MosquittoLoopRead(i32),
+
     /// MosquittoLoopWrite error.
     MosquittoLoopWrite(i32),
 
@@ -52,6 +58,84 @@ pub enum Error {
     /// MosquittoTlsSet error.
     MosquittoTlsSet(i32),
 
+    /// MosquittoTlsOptsSet error.
+    MosquittoTlsOptsSet(i32),
+
+    /// MosquittoTlsInsecureSet error.
+    MosquittoTlsInsecureSet(i32),
+
+    /// MosquittoTlsPskSet error.
+    MosquittoTlsPskSet(i32),
+
+    /// MosquittoSocks5Set error.
+    MosquittoSocks5Set(i32),
+
+    /// MosquittoOption error.
+    MosquittoOption(i32),
+
+    /// MosquittoProperty error.
+    MosquittoProperty(i32),
+
+    /// MosquittoWillSet error.
+    MosquittoWillSet(i32),
+
+    /// MosquittoWillClear error.
+    MosquittoWillClear(i32),
+
+    /// MosquittoReconnectDelaySet error.
+    MosquittoReconnectDelaySet(i32),
+
+    /// MosquittoThreadedSet error.
+    MosquittoThreadedSet(i32),
+
+    /// Broker refused the connection.
+    ConnectionRefused(crate::model::ConnectReason),
+
+    /// Connection was lost before the operation completed.
+    ConnectionLost,
+
+    /// Broker rejected the subscription.
+    SubscriptionRejected(i32),
+
+    /// Request got no reply in time.
+    RequestTimeout,
+
+    /// Publish was not acknowledged in time.
+    DeliveryTimeout,
+
+    /// Message was dropped from the offline queue.
+    MessageDropped,
+
+    /// Callback panicked; the panic was caught before reaching libmosquitto.
+    CallbackPanic(String, String),
+
+    /// Payload of message on topic could not be decoded.
+    Decode(String, Box<dyn std::error::Error>),
+
+    /// Message has no response topic to reply to.
+    MissingResponseTopic,
+
+    /// Path is not valid UTF-8.
+    InvalidPath(std::path::PathBuf),
+
+    /// Pre-shared key is not a valid hex string.
+    InvalidPsk,
+
+    /// Topic name or filter is not valid.
+    InvalidTopic(String),
+
+    /// Client id can not be used as a topic level, e.g. for the response topic.
+    InvalidClientId(String),
+
+    /// Client certificate given without a CA file or CA directory.
+    ClientCertificateWithoutCa,
+
+    /// PEM data can not be written with owner-only permissions on this platform.
+    InsecurePemFile,
+
+    /// Client is driven by a network loop of the application, see set_threaded.
+    ExternalLoop,
+
     /// Any boxed error.
     Boxed(Box<dyn std::error::Error>),
 
@@ -75,9 +159,13 @@ pub enum Error {
 }
 
 impl From<Box<dyn std::error::Error>> for Error {
-    /// A source containing any boxed error.
+    /// A source containing any boxed error. A boxed `Error` is unwrapped rather
+    /// than nested, so it can still be matched on.
     fn from(error: Box<dyn std::error::Error>) -> Self {
-        Error::Boxed(error)
+        match error.downcast::<Error>() {
+            Ok(error) => *error,
+            Err(error) => Error::Boxed(error),
+        }
     }
 }
 
@@ -147,6 +235,14 @@ impl std::fmt::Display for Error {
                 "MosquittoMqttLoopStart error - status code: {}",
                 status_code
             ),
+            Error::MosquittoMqttLoopStop(status_code) => write!(
+                f,
+                "MosquittoMqttLoopStop error - status code: {}",
+                status_code
+            ),
+            Error::MosquittoLoopRead(status_code) => {
+                write!(f, "MosquittoLoopRead error - status code: {}", status_code)
+            }
             Error::MosquittoLoopWrite(status_code) => {
                 write!(f, "MosquittoLoopWrite error - status code: {}", status_code)
             }
@@ -157,6 +253,74 @@ impl std::fmt::Display for Error {
             Error::MosquittoTlsSet(status_code) => {
                 write!(f, "MosquittoTlsSet error - status code: {}", status_code)
             }
+            Error::MosquittoTlsOptsSet(status_code) => {
+                write!(
+                    f,
+                    "MosquittoTlsOptsSet error - status code: {}",
+                    status_code
+                )
+            }
+            Error::MosquittoTlsInsecureSet(status_code) => write!(
+                f,
+                "MosquittoTlsInsecureSet error - status code: {}",
+                status_code
+            ),
+            Error::MosquittoTlsPskSet(status_code) => {
+                write!(f, "MosquittoTlsPskSet error - status code: {}", status_code)
+            }
+            Error::MosquittoSocks5Set(status_code) => {
+                write!(f, "MosquittoSocks5Set error - status code: {}", status_code)
+            }
+            Error::MosquittoOption(status_code) => {
+                write!(f, "MosquittoOption error - status code: {}", status_code)
+            }
+            Error::MosquittoProperty(status_code) => {
+                write!(f, "MosquittoProperty error - status code: {}", status_code)
+            }
+            Error::MosquittoWillSet(status_code) => {
+                write!(f, "MosquittoWillSet error - status code: {}", status_code)
+            }
+            Error::MosquittoWillClear(status_code) => {
+                write!(f, "MosquittoWillClear error - status code: {}", status_code)
+            }
+            Error::MosquittoReconnectDelaySet(status_code) => write!(
+                f,
+                "MosquittoReconnectDelaySet error - status code: {}",
+                status_code
+            ),
+            Error::MosquittoThreadedSet(status_code) => {
+                write!(
+                    f,
+                    "MosquittoThreadedSet error - status code: {}",
+                    status_code
+                )
+            }
+            Error::ConnectionRefused(reason) => write!(f, "Connection refused: {}", reason),
+            Error::ConnectionLost => write!(f, "Connection lost"),
+            Error::SubscriptionRejected(reason_code) => {
+                write!(f, "Subscription rejected - reason code: {}", reason_code)
+            }
+            Error::RequestTimeout => write!(f, "Request timed out"),
+            Error::DeliveryTimeout => write!(f, "Delivery timed out"),
+            Error::MessageDropped => write!(f, "Message dropped from offline queue"),
+            Error::CallbackPanic(callback, message) => {
+                write!(f, "Panic in {}: {}", callback, message)
+            }
+            Error::Decode(topic, error) => {
+                write!(f, "Could not decode message on {}: {}", topic, error)
+            }
+            Error::MissingResponseTopic => write!(f, "Message has no response topic"),
+            Error::InvalidPath(path) => write!(f, "Invalid path: {}", path.display()),
+            Error::InvalidPsk => write!(f, "Pre-shared key is not a valid hex string"),
+            Error::InvalidTopic(topic) => write!(f, "Invalid topic: {}", topic),
+            Error::InvalidClientId(id) => write!(f, "Invalid client id for topic: {}", id),
+            Error::ClientCertificateWithoutCa => {
+                write!(f, "Client certificate requires a CA file or CA directory")
+            }
+            Error::InsecurePemFile => {
+                write!(f, "PEM data requires owner-only file permissions")
+            }
+            Error::ExternalLoop => write!(f, "Network loop is run by the application"),
             Error::CString => write!(f, "CString error"),
             Error::Boxed(error) => write!(f, "{}", error),
             Error::Io(error) => write!(f, "{}", error),
@@ -0,0 +1,44 @@
+use crate::Mosquitto;
+use mio::event::Source;
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+use std::io;
+
+/// Registers the client socket with a mio poller.
+///
+/// On readable events call loop_read, on writable events loop_write, and call
+/// loop_misc at least once per second. Register WRITABLE interest only while
+/// loop_want_write returns true. The socket changes on reconnect, so deregister
+/// before and register again after reconnecting.
+impl Source for Mosquitto {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        let fd = socket_fd(self)?;
+        SourceFd(&fd).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        let fd = socket_fd(self)?;
+        SourceFd(&fd).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        let fd = socket_fd(self)?;
+        SourceFd(&fd).deregister(registry)
+    }
+}
+
+fn socket_fd(mosquitto: &Mosquitto) -> io::Result<i32> {
+    mosquitto
+        .socket()
+        .map_err(|err| io::Error::new(io::ErrorKind::NotConnected, err.to_string()))
+}
@@ -1,9 +1,34 @@
+#[cfg(all(feature = "tokio", unix))]
+mod async_client;
 mod callback;
+mod channel;
+mod client;
+mod client_handle;
+mod delivery;
 mod error;
+#[cfg(all(feature = "mio", unix))]
+mod event_source;
 mod model;
 mod mosquitto;
+mod offline_queue;
+mod property;
+mod proxy;
+mod router;
+mod rpc;
+mod subscription;
+mod sync;
+mod tls;
 
+#[cfg(all(feature = "tokio", unix))]
+pub use self::async_client::*;
 pub use self::callback::*;
+pub use self::channel::*;
+pub use self::client::*;
+pub use self::client_handle::*;
+pub use self::delivery::DeliveryToken;
 pub use self::error::*;
 pub use self::model::*;
 pub use self::mosquitto::*;
+pub use self::offline_queue::OfflineQueueConfig;
+pub use self::proxy::*;
+pub use self::tls::*;
@@ -1,5 +1,22 @@
+mod client_option;
+mod codec;
+mod connect_reason;
 mod message;
+mod protocol;
+mod qos;
+mod subscribe_options;
+mod topic;
+mod topic_tree;
 mod version;
 
+pub use self::client_option::ClientOption;
+pub(crate) use self::client_option::OptionValue;
+pub use self::codec::*;
+pub use self::connect_reason::*;
 pub use self::message::*;
+pub use self::protocol::*;
+pub use self::qos::*;
+pub use self::subscribe_options::*;
+pub use self::topic::*;
+pub use self::topic_tree::*;
 pub use self::version::*;
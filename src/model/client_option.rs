@@ -0,0 +1,124 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+
+use crate::model::ProtocolVersion;
+
+/// Client option set through mosquitto_int_option or mosquitto_string_option.
+/// Options must be set before connect.
+///
+/// An OpenSSL context (MOSQ_OPT_SSL_CTX) is set with `Mosquitto::set_ssl_ctx`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum ClientOption {
+    /// MQTT protocol version.
+    ProtocolVersion(ProtocolVersion),
+    /// Initialise an OpenSSL context given by set_ssl_ctx with default options.
+    SslCtxWithDefaults(bool),
+    /// MQTT v5 receive maximum, the number of QoS 1 and 2 messages processed at once.
+    ReceiveMaximum(u16),
+    /// MQTT v5 send maximum, the number of QoS 1 and 2 messages sent at once.
+    SendMaximum(u16),
+    /// Format of the TLS client key, `pem` or `engine`.
+    TlsKeyform(String),
+    /// OpenSSL engine used for TLS.
+    TlsEngine(String),
+    /// SHA1 of the private key password for the OpenSSL engine.
+    TlsEngineKpassSha1(String),
+    /// Require OCSP stapling of the broker certificate.
+    TlsOcspRequired(bool),
+    /// ALPN protocol announced during the TLS handshake.
+    TlsAlpn(String),
+    /// Disable Nagle's algorithm on the broker connection.
+    TcpNoDelay(bool),
+    /// Local address to bind the broker connection to.
+    BindAddress(String),
+    /// Trust the certificates of the OS trust store.
+    TlsUseOsCerts(bool),
+}
+
+impl std::fmt::Debug for ClientOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (name, value): (&str, &dyn std::fmt::Debug) = match self {
+            ClientOption::ProtocolVersion(value) => ("ProtocolVersion", value),
+            ClientOption::SslCtxWithDefaults(value) => ("SslCtxWithDefaults", value),
+            ClientOption::ReceiveMaximum(value) => ("ReceiveMaximum", value),
+            ClientOption::SendMaximum(value) => ("SendMaximum", value),
+            ClientOption::TlsKeyform(value) => ("TlsKeyform", value),
+            ClientOption::TlsEngine(value) => ("TlsEngine", value),
+            ClientOption::TlsEngineKpassSha1(_) => ("TlsEngineKpassSha1", &"<redacted>"),
+            ClientOption::TlsOcspRequired(value) => ("TlsOcspRequired", value),
+            ClientOption::TlsAlpn(value) => ("TlsAlpn", value),
+            ClientOption::TcpNoDelay(value) => ("TcpNoDelay", value),
+            ClientOption::BindAddress(value) => ("BindAddress", value),
+            ClientOption::TlsUseOsCerts(value) => ("TlsUseOsCerts", value),
+        };
+        f.debug_tuple(name).field(value).finish()
+    }
+}
+
+/// Value of a client option as passed to libmosquitto.
+pub(crate) enum OptionValue<'a> {
+    Int(i32),
+    String(&'a str),
+}
+
+impl ClientOption {
+    /// Return option as expected by libmosquitto.
+    pub fn as_raw(&self) -> mqtt::mosq_opt_t {
+        match self {
+            ClientOption::ProtocolVersion(_) => mqtt::mosq_opt_t_MOSQ_OPT_PROTOCOL_VERSION,
+            ClientOption::SslCtxWithDefaults(_) => mqtt::mosq_opt_t_MOSQ_OPT_SSL_CTX_WITH_DEFAULTS,
+            ClientOption::ReceiveMaximum(_) => mqtt::mosq_opt_t_MOSQ_OPT_RECEIVE_MAXIMUM,
+            ClientOption::SendMaximum(_) => mqtt::mosq_opt_t_MOSQ_OPT_SEND_MAXIMUM,
+            ClientOption::TlsKeyform(_) => mqtt::mosq_opt_t_MOSQ_OPT_TLS_KEYFORM,
+            ClientOption::TlsEngine(_) => mqtt::mosq_opt_t_MOSQ_OPT_TLS_ENGINE,
+            ClientOption::TlsEngineKpassSha1(_) => mqtt::mosq_opt_t_MOSQ_OPT_TLS_ENGINE_KPASS_SHA1,
+            ClientOption::TlsOcspRequired(_) => mqtt::mosq_opt_t_MOSQ_OPT_TLS_OCSP_REQUIRED,
+            ClientOption::TlsAlpn(_) => mqtt::mosq_opt_t_MOSQ_OPT_TLS_ALPN,
+            ClientOption::TcpNoDelay(_) => mqtt::mosq_opt_t_MOSQ_OPT_TCP_NODELAY,
+            ClientOption::BindAddress(_) => mqtt::mosq_opt_t_MOSQ_OPT_BIND_ADDRESS,
+            ClientOption::TlsUseOsCerts(_) => mqtt::mosq_opt_t_MOSQ_OPT_TLS_USE_OS_CERTS,
+        }
+    }
+
+    pub(crate) fn value(&self) -> OptionValue<'_> {
+        match self {
+            ClientOption::ProtocolVersion(version) => OptionValue::Int(version.as_raw()),
+            ClientOption::ReceiveMaximum(value) | ClientOption::SendMaximum(value) => {
+                OptionValue::Int(i32::from(*value))
+            }
+            ClientOption::SslCtxWithDefaults(value)
+            | ClientOption::TlsOcspRequired(value)
+            | ClientOption::TcpNoDelay(value)
+            | ClientOption::TlsUseOsCerts(value) => OptionValue::Int(i32::from(*value)),
+            ClientOption::TlsKeyform(value)
+            | ClientOption::TlsEngine(value)
+            | ClientOption::TlsEngineKpassSha1(value)
+            | ClientOption::TlsAlpn(value)
+            | ClientOption::BindAddress(value) => OptionValue::String(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_engine_password() {
+        let option = ClientOption::TlsEngineKpassSha1("0123456789abcdef".to_string());
+        let debug = format!("{:?}", option);
+        assert!(!debug.contains("0123456789abcdef"));
+        assert_eq!(debug, "TlsEngineKpassSha1(\"<redacted>\")");
+    }
+
+    #[test]
+    fn debug_keeps_other_values() {
+        assert_eq!(
+            format!("{:?}", ClientOption::TlsAlpn("mqtt".to_string())),
+            "TlsAlpn(\"mqtt\")"
+        );
+        assert_eq!(
+            format!("{:?}", ClientOption::ReceiveMaximum(10)),
+            "ReceiveMaximum(10)"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::model::{Message, TopicTree};
+use crate::{Error, Result};
+use std::sync::Arc;
+
+/// Encodes values of type T into payloads and decodes them back.
+pub trait Codec<T> {
+    /// Return MQTT v5 content type identifying payloads of this codec.
+    fn content_type(&self) -> &str;
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>>;
+
+    fn decode(&self, payload: &[u8]) -> Result<T>;
+}
+
+/// JSON codec.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "serde")]
+impl<T> Codec<T> for JsonCodec
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T> {
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+/// CBOR codec.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl<T> Codec<T> for CborCodec
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn content_type(&self) -> &str {
+        "application/cbor"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        ciborium::ser::into_writer(value, &mut payload)?;
+        Ok(payload)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T> {
+        Ok(ciborium::de::from_reader(payload)?)
+    }
+}
+
+/// MessagePack codec. Structs are encoded as maps keyed by field name.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl<T> Codec<T> for MessagePackCodec
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn content_type(&self) -> &str {
+        "application/msgpack"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T> {
+        Ok(rmp_serde::from_slice(payload)?)
+    }
+}
+
+/// Protocol Buffers codec for prost messages.
+#[cfg(feature = "protobuf")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProtobufCodec;
+
+#[cfg(feature = "protobuf")]
+impl<T> Codec<T> for ProtobufCodec
+where
+    T: prost::Message + Default,
+{
+    fn content_type(&self) -> &str {
+        "application/protobuf"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T> {
+        Ok(T::decode(payload)?)
+    }
+}
+
+type SharedCodec<T> = Arc<dyn Codec<T> + Send + Sync>;
+
+/// Codecs to decode incoming messages with.
+///
+/// A codec is chosen by the MQTT v5 content type of a message first, then by the
+/// most specific topic filter matching its topic, then the default codec. Filters
+/// are compared level by level, an exact level beats `+`, which beats `#`; e.g.
+/// `sensors/temp` is preferred over `sensors/+` and `sensors/#`.
+pub struct Codecs<T> {
+    by_content_type: Vec<SharedCodec<T>>,
+    by_filter: TopicTree<SharedCodec<T>>,
+    default: Option<SharedCodec<T>>,
+}
+
+impl<T> Default for Codecs<T> {
+    fn default() -> Self {
+        Codecs {
+            by_content_type: Vec::new(),
+            by_filter: TopicTree::new(),
+            default: None,
+        }
+    }
+}
+
+impl<T> Codecs<T> {
+    /// Decode all messages with codec, unless their content type selects another.
+    pub fn new<C>(codec: C) -> Self
+    where
+        C: Codec<T> + Send + Sync + 'static,
+    {
+        Codecs {
+            default: Some(Arc::new(codec)),
+            ..Codecs::default()
+        }
+    }
+
+    /// Decode messages carrying the content type of codec with it.
+    pub fn content_type<C>(mut self, codec: C) -> Self
+    where
+        C: Codec<T> + Send + Sync + 'static,
+    {
+        self.by_content_type.push(Arc::new(codec));
+        self
+    }
+
+    /// Decode messages on topics matching filter with codec.
+    pub fn topic_filter<C>(mut self, filter: &str, codec: C) -> Self
+    where
+        C: Codec<T> + Send + Sync + 'static,
+    {
+        self.by_filter.insert(filter, Arc::new(codec));
+        self
+    }
+
+    /// Return codec for message, if any.
+    pub fn select(&self, message: &Message) -> Option<&(dyn Codec<T> + Send + Sync)> {
+        if let Some(content_type) = message.content_type() {
+            let codec = self
+                .by_content_type
+                .iter()
+                .find(|codec| codec.content_type() == content_type);
+            if let Some(codec) = codec {
+                return Some(codec.as_ref());
+            }
+        }
+        if let Some(codec) = self.by_filter.best_match(message.topic()) {
+            return Some(codec.as_ref());
+        }
+        self.default.as_deref()
+    }
+
+    /// Decode payload of message with the codec selected for it.
+    pub fn decode(&self, message: &Message) -> Result<T> {
+        let codec = self.select(message).ok_or_else(|| {
+            Error::Decode(message.topic().to_string(), "no codec for message".into())
+        })?;
+        codec
+            .decode(message.payload())
+            .map_err(|err| Error::Decode(message.topic().to_string(), err).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Tagged(&'static str);
+
+    impl Codec<String> for Tagged {
+        fn content_type(&self) -> &str {
+            self.0
+        }
+
+        fn encode(&self, value: &String) -> Result<Vec<u8>> {
+            Ok(value.as_bytes().to_vec())
+        }
+
+        fn decode(&self, _payload: &[u8]) -> Result<String> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    fn decode(codecs: &Codecs<String>, message: &Message) -> String {
+        codecs.decode(message).unwrap()
+    }
+
+    #[test]
+    fn selects_most_specific_filter() {
+        let codecs = Codecs::new(Tagged("default"))
+            .topic_filter("sensors/#", Tagged("any"))
+            .topic_filter("sensors/temp", Tagged("temp"))
+            .topic_filter("sensors/+", Tagged("level"));
+        assert_eq!(decode(&codecs, &Message::new("sensors/temp", b"")), "temp");
+        assert_eq!(decode(&codecs, &Message::new("sensors/hum", b"")), "level");
+        assert_eq!(decode(&codecs, &Message::new("sensors/a/b", b"")), "any");
+        assert_eq!(decode(&codecs, &Message::new("other", b"")), "default");
+    }
+
+    #[test]
+    fn content_type_takes_precedence() {
+        let codecs = Codecs::default()
+            .content_type(Tagged("application/cbor"))
+            .topic_filter("sensors/temp", Tagged("temp"));
+        let message =
+            Message::new("sensors/temp", b"").with_content_type(Some("application/cbor".into()));
+        assert_eq!(decode(&codecs, &message), "application/cbor");
+
+        let message =
+            Message::new("sensors/temp", b"").with_content_type(Some("text/plain".into()));
+        assert_eq!(decode(&codecs, &message), "temp");
+    }
+
+    #[test]
+    fn fails_without_codec() {
+        let codecs: Codecs<String> = Codecs::default().topic_filter("a", Tagged("a"));
+        assert!(codecs.decode(&Message::new("b", b"")).is_err());
+    }
+
+    #[test]
+    fn decode_error_converts_to_decode_variant() {
+        let codecs: Codecs<String> = Codecs::default();
+        let err = codecs.decode(&Message::new("b", b"")).unwrap_err();
+        assert!(matches!(Error::from(err), Error::Decode(topic, _) if topic == "b"));
+    }
+}
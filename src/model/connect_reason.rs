@@ -0,0 +1,233 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+use std::ffi::CStr;
+
+/// Decoded CONNACK code reported to the connect callback.
+/// MQTT v3.1.1 return codes and MQTT v5 reason codes do not overlap, so both are
+/// decoded from the same value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectReason {
+    /// Connection accepted.
+    Accepted,
+    /// v3.1.1: Unacceptable protocol version.
+    UnacceptableProtocol,
+    /// v3.1.1: Identifier rejected.
+    IdentifierRejected,
+    /// v3.1.1: Server unavailable.
+    ServerUnavailable,
+    /// v3.1.1: Bad user name or password.
+    BadCredentials,
+    /// v3.1.1: Not authorized.
+    NotAuthorized,
+    /// v5: Unspecified error.
+    UnspecifiedError,
+    /// v5: Malformed packet.
+    MalformedPacket,
+    /// v5: Protocol error.
+    ProtocolError,
+    /// v5: Implementation specific error.
+    ImplementationSpecificError,
+    /// v5: Unsupported protocol version.
+    UnsupportedProtocolVersion,
+    /// v5: Client identifier not valid.
+    ClientIdentifierNotValid,
+    /// v5: Bad user name or password.
+    BadUsernameOrPassword,
+    /// v5: Not authorized.
+    NotAuthorizedV5,
+    /// v5: Server unavailable.
+    ServerUnavailableV5,
+    /// v5: Server busy.
+    ServerBusy,
+    /// v5: Banned.
+    Banned,
+    /// v5: Bad authentication method.
+    BadAuthenticationMethod,
+    /// v5: Topic name invalid.
+    TopicNameInvalid,
+    /// v5: Packet too large.
+    PacketTooLarge,
+    /// v5: Quota exceeded.
+    QuotaExceeded,
+    /// v5: Payload format invalid.
+    PayloadFormatInvalid,
+    /// v5: Retain not supported.
+    RetainNotSupported,
+    /// v5: QoS not supported.
+    QosNotSupported,
+    /// v5: Use another server.
+    UseAnotherServer,
+    /// v5: Server moved.
+    ServerMoved,
+    /// v5: Connection rate exceeded.
+    ConnectionRateExceeded,
+    /// Code not known to this crate.
+    Other(i32),
+}
+
+impl ConnectReason {
+    /// Decode CONNACK code.
+    pub fn from_code(code: i32) -> Self {
+        match code {
+            0 => ConnectReason::Accepted,
+            1 => ConnectReason::UnacceptableProtocol,
+            2 => ConnectReason::IdentifierRejected,
+            3 => ConnectReason::ServerUnavailable,
+            4 => ConnectReason::BadCredentials,
+            5 => ConnectReason::NotAuthorized,
+            0x80 => ConnectReason::UnspecifiedError,
+            0x81 => ConnectReason::MalformedPacket,
+            0x82 => ConnectReason::ProtocolError,
+            0x83 => ConnectReason::ImplementationSpecificError,
+            0x84 => ConnectReason::UnsupportedProtocolVersion,
+            0x85 => ConnectReason::ClientIdentifierNotValid,
+            0x86 => ConnectReason::BadUsernameOrPassword,
+            0x87 => ConnectReason::NotAuthorizedV5,
+            0x88 => ConnectReason::ServerUnavailableV5,
+            0x89 => ConnectReason::ServerBusy,
+            0x8A => ConnectReason::Banned,
+            0x8C => ConnectReason::BadAuthenticationMethod,
+            0x90 => ConnectReason::TopicNameInvalid,
+            0x95 => ConnectReason::PacketTooLarge,
+            0x97 => ConnectReason::QuotaExceeded,
+            0x99 => ConnectReason::PayloadFormatInvalid,
+            0x9A => ConnectReason::RetainNotSupported,
+            0x9B => ConnectReason::QosNotSupported,
+            0x9C => ConnectReason::UseAnotherServer,
+            0x9D => ConnectReason::ServerMoved,
+            0x9F => ConnectReason::ConnectionRateExceeded,
+            other => ConnectReason::Other(other),
+        }
+    }
+
+    /// Return raw CONNACK code.
+    pub fn code(&self) -> i32 {
+        match self {
+            ConnectReason::Accepted => 0,
+            ConnectReason::UnacceptableProtocol => 1,
+            ConnectReason::IdentifierRejected => 2,
+            ConnectReason::ServerUnavailable => 3,
+            ConnectReason::BadCredentials => 4,
+            ConnectReason::NotAuthorized => 5,
+            ConnectReason::UnspecifiedError => 0x80,
+            ConnectReason::MalformedPacket => 0x81,
+            ConnectReason::ProtocolError => 0x82,
+            ConnectReason::ImplementationSpecificError => 0x83,
+            ConnectReason::UnsupportedProtocolVersion => 0x84,
+            ConnectReason::ClientIdentifierNotValid => 0x85,
+            ConnectReason::BadUsernameOrPassword => 0x86,
+            ConnectReason::NotAuthorizedV5 => 0x87,
+            ConnectReason::ServerUnavailableV5 => 0x88,
+            ConnectReason::ServerBusy => 0x89,
+            ConnectReason::Banned => 0x8A,
+            ConnectReason::BadAuthenticationMethod => 0x8C,
+            ConnectReason::TopicNameInvalid => 0x90,
+            ConnectReason::PacketTooLarge => 0x95,
+            ConnectReason::QuotaExceeded => 0x97,
+            ConnectReason::PayloadFormatInvalid => 0x99,
+            ConnectReason::RetainNotSupported => 0x9A,
+            ConnectReason::QosNotSupported => 0x9B,
+            ConnectReason::UseAnotherServer => 0x9C,
+            ConnectReason::ServerMoved => 0x9D,
+            ConnectReason::ConnectionRateExceeded => 0x9F,
+            ConnectReason::Other(code) => *code,
+        }
+    }
+
+    /// Return true if connection was accepted.
+    pub fn is_accepted(&self) -> bool {
+        *self == ConnectReason::Accepted
+    }
+
+    /// Return true if broker refused the client credentials or authorization.
+    /// Retrying with the same configuration will not succeed.
+    pub fn is_auth_error(&self) -> bool {
+        matches!(
+            self,
+            ConnectReason::BadCredentials
+                | ConnectReason::NotAuthorized
+                | ConnectReason::BadUsernameOrPassword
+                | ConnectReason::NotAuthorizedV5
+                | ConnectReason::Banned
+                | ConnectReason::BadAuthenticationMethod
+        )
+    }
+
+    /// Return true if the refusal is transient and connecting again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ConnectReason::ServerUnavailable
+                | ConnectReason::ServerUnavailableV5
+                | ConnectReason::ServerBusy
+                | ConnectReason::QuotaExceeded
+                | ConnectReason::ConnectionRateExceeded
+                | ConnectReason::UseAnotherServer
+                | ConnectReason::ServerMoved
+        )
+    }
+
+    /// Return libmosquitto description of the code.
+    pub fn description(&self) -> String {
+        let code = self.code();
+        unsafe {
+            let text = if (0..0x80).contains(&code) {
+                mqtt::mosquitto_connack_string(code)
+            } else {
+                mqtt::mosquitto_reason_string(code)
+            };
+            if text.is_null() {
+                return format!("Unknown reason code {}", code);
+            }
+            CStr::from_ptr(text).to_string_lossy().into_owned()
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.description(), self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_v311_and_v5_codes() {
+        assert_eq!(ConnectReason::from_code(0), ConnectReason::Accepted);
+        assert_eq!(ConnectReason::from_code(4), ConnectReason::BadCredentials);
+        assert_eq!(
+            ConnectReason::from_code(0x86),
+            ConnectReason::BadUsernameOrPassword
+        );
+        assert_eq!(
+            ConnectReason::from_code(0x9F),
+            ConnectReason::ConnectionRateExceeded
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_codes() {
+        assert_eq!(ConnectReason::from_code(6), ConnectReason::Other(6));
+        assert_eq!(ConnectReason::from_code(0x8B), ConnectReason::Other(0x8B));
+    }
+
+    #[test]
+    fn code_round_trips() {
+        for code in (0..=5).chain(0x80..=0xA2) {
+            assert_eq!(ConnectReason::from_code(code).code(), code);
+        }
+    }
+
+    #[test]
+    fn classifies_refusals() {
+        assert!(ConnectReason::Accepted.is_accepted());
+        assert!(ConnectReason::from_code(5).is_auth_error());
+        assert!(ConnectReason::from_code(0x8A).is_auth_error());
+        assert!(!ConnectReason::from_code(0x8A).is_retryable());
+        assert!(ConnectReason::from_code(3).is_retryable());
+        assert!(ConnectReason::from_code(0x89).is_retryable());
+        assert!(!ConnectReason::from_code(0x81).is_retryable());
+    }
+}
@@ -1,22 +1,66 @@
 #[derive(Debug)]
 pub struct Message {
     topic: String,
-    payload: String,
+    payload: Vec<u8>,
+    content_type: Option<String>,
+    response_topic: Option<String>,
+    correlation_data: Option<Vec<u8>>,
 }
 
 impl Message {
-    pub fn new(topic: &str, payload: &str) -> Self {
+    pub fn new(topic: &str, payload: &[u8]) -> Self {
         Message {
             topic: topic.to_string(),
-            payload: payload.to_string(),
+            payload: payload.to_vec(),
+            content_type: None,
+            response_topic: None,
+            correlation_data: None,
         }
     }
 
+    /// Sets MQTT v5 content type.
+    pub fn with_content_type(mut self, content_type: Option<String>) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    /// Sets MQTT v5 response topic.
+    pub fn with_response_topic(mut self, response_topic: Option<String>) -> Self {
+        self.response_topic = response_topic;
+        self
+    }
+
+    /// Sets MQTT v5 correlation data.
+    pub fn with_correlation_data(mut self, correlation_data: Option<Vec<u8>>) -> Self {
+        self.correlation_data = correlation_data;
+        self
+    }
+
     pub fn topic(&self) -> &str {
         &self.topic
     }
 
-    pub fn payload(&self) -> &str {
+    pub fn payload(&self) -> &[u8] {
         &self.payload
     }
+
+    /// Return payload as string, None if it is not valid UTF-8.
+    pub fn payload_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.payload).ok()
+    }
+
+    /// Return MQTT v5 content type of the payload.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Return MQTT v5 response topic, if the sender expects a reply.
+    pub fn response_topic(&self) -> Option<&str> {
+        self.response_topic.as_deref()
+    }
+
+    /// Return MQTT v5 correlation data.
+    pub fn correlation_data(&self) -> Option<&[u8]> {
+        self.correlation_data.as_deref()
+    }
 }
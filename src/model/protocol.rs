@@ -0,0 +1,20 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+
+/// MQTT protocol version used when connecting to broker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V31,
+    V311,
+    V5,
+}
+
+impl ProtocolVersion {
+    /// Return protocol version as expected by libmosquitto.
+    pub fn as_raw(self) -> i32 {
+        match self {
+            ProtocolVersion::V31 => mqtt::MQTT_PROTOCOL_V31 as i32,
+            ProtocolVersion::V311 => mqtt::MQTT_PROTOCOL_V311 as i32,
+            ProtocolVersion::V5 => mqtt::MQTT_PROTOCOL_V5 as i32,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+
+/// When broker sends retained messages for a new subscription (MQTT v5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetainHandling {
+    /// Always send retained messages.
+    #[default]
+    SendAlways,
+    /// Send retained messages only if the subscription did not exist before.
+    SendNew,
+    /// Never send retained messages.
+    SendNever,
+}
+
+/// MQTT v5 subscription options. Only the defaults are valid for older protocols.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubscribeOptions {
+    /// Do not receive messages published by this client.
+    pub no_local: bool,
+    /// Keep the retain flag of forwarded messages.
+    pub retain_as_published: bool,
+    pub retain_handling: RetainHandling,
+}
+
+impl SubscribeOptions {
+    /// Return options as expected by libmosquitto.
+    pub fn as_raw(&self) -> i32 {
+        let mut options = match self.retain_handling {
+            RetainHandling::SendAlways => mqtt::mqtt5_sub_options_MQTT_SUB_OPT_SEND_RETAIN_ALWAYS,
+            RetainHandling::SendNew => mqtt::mqtt5_sub_options_MQTT_SUB_OPT_SEND_RETAIN_NEW,
+            RetainHandling::SendNever => mqtt::mqtt5_sub_options_MQTT_SUB_OPT_SEND_RETAIN_NEVER,
+        };
+        if self.no_local {
+            options |= mqtt::mqtt5_sub_options_MQTT_SUB_OPT_NO_LOCAL;
+        }
+        if self.retain_as_published {
+            options |= mqtt::mqtt5_sub_options_MQTT_SUB_OPT_RETAIN_AS_PUBLISHED;
+        }
+        options as i32
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{Error, Result};
+
+/// Maximum length of a topic in bytes.
+const MAX_TOPIC_LEN: usize = 65535;
+
+/// Validated topic name to publish to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Topic(String);
+
+/// Validated topic filter to subscribe to, may contain `+` and `#` wildcards.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicFilter(String);
+
+impl Topic {
+    /// Validate topic name like mosquitto_pub_topic_check2: non empty, at most 65535
+    /// bytes, valid MQTT UTF-8 and free of wildcards.
+    pub fn new(topic: &str) -> Result<Self> {
+        check_common(topic)?;
+        if topic.contains(['+', '#']) {
+            return Err(Error::InvalidTopic(topic.to_string()).into());
+        }
+        Ok(Topic(topic.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Return topic levels, like mosquitto_sub_topic_tokenise.
+    pub fn levels(&self) -> Vec<&str> {
+        self.0.split('/').collect()
+    }
+}
+
+impl TopicFilter {
+    /// Validate topic filter like mosquitto_sub_topic_check2: non empty, at most 65535
+    /// bytes, valid MQTT UTF-8, `+` only as a whole level and `#` only as the whole
+    /// last level.
+    pub fn new(filter: &str) -> Result<Self> {
+        check_common(filter)?;
+        let levels: Vec<&str> = filter.split('/').collect();
+        for (index, level) in levels.iter().enumerate() {
+            let last = index + 1 == levels.len();
+            let valid = match *level {
+                "+" => true,
+                "#" => last,
+                level => !level.contains(['+', '#']),
+            };
+            if !valid {
+                return Err(Error::InvalidTopic(filter.to_string()).into());
+            }
+        }
+        Ok(TopicFilter(filter.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Return filter levels, like mosquitto_sub_topic_tokenise.
+    pub fn levels(&self) -> Vec<&str> {
+        self.0.split('/').collect()
+    }
+
+    /// Return true if topic matches this filter.
+    pub fn matches(&self, topic: &Topic) -> bool {
+        topic_matches(&self.0, &topic.0)
+    }
+}
+
+impl AsRef<str> for Topic {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for TopicFilter {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Topic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::fmt::Display for TopicFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn check_common(topic: &str) -> Result<()> {
+    if topic.is_empty() || topic.len() > MAX_TOPIC_LEN || !topic.chars().all(valid_char) {
+        return Err(Error::InvalidTopic(topic.to_string()).into());
+    }
+    Ok(())
+}
+
+/// Return true if topic matches subscription filter, supporting `+` and `#`
+/// wildcards. Topics starting with `$` are not matched by a leading wildcard.
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(filter_level), Some(topic_level)) if filter_level == topic_level => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Characters accepted by mosquitto_validate_utf8.
+fn valid_char(c: char) -> bool {
+    let code = c as u32;
+    !(code <= 0x1F
+        || (0x7F..=0x9F).contains(&code)
+        || (0xFDD0..=0xFDEF).contains(&code)
+        || code & 0xFFFE == 0xFFFE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_topics() {
+        assert!(Topic::new("a").is_ok());
+        assert!(Topic::new("a/b/c").is_ok());
+        assert!(Topic::new("/a//b/").is_ok());
+        assert!(Topic::new("$SYS/broker").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_topics() {
+        assert!(Topic::new("").is_err());
+        assert!(Topic::new("a/+/b").is_err());
+        assert!(Topic::new("a/#").is_err());
+        assert!(Topic::new("a\u{0}b").is_err());
+        assert!(Topic::new("a\u{1F}b").is_err());
+        assert!(Topic::new("a\u{FFFF}b").is_err());
+        assert!(Topic::new(&"a".repeat(MAX_TOPIC_LEN + 1)).is_err());
+        assert!(Topic::new(&"a".repeat(MAX_TOPIC_LEN)).is_ok());
+    }
+
+    #[test]
+    fn accepts_valid_filters() {
+        assert!(TopicFilter::new("#").is_ok());
+        assert!(TopicFilter::new("+").is_ok());
+        assert!(TopicFilter::new("a/+/b").is_ok());
+        assert!(TopicFilter::new("a/b/#").is_ok());
+        assert!(TopicFilter::new("+/+/#").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        assert!(TopicFilter::new("").is_err());
+        assert!(TopicFilter::new("a/#/b").is_err());
+        assert!(TopicFilter::new("a#").is_err());
+        assert!(TopicFilter::new("a/b+").is_err());
+        assert!(TopicFilter::new("a/+b/c").is_err());
+    }
+
+    #[test]
+    fn splits_levels() {
+        let topic = Topic::new("a//b").unwrap();
+        assert_eq!(topic.levels(), vec!["a", "", "b"]);
+        let filter = TopicFilter::new("a/+/#").unwrap();
+        assert_eq!(filter.levels(), vec!["a", "+", "#"]);
+    }
+
+    #[test]
+    fn filter_matches_topic() {
+        let filter = TopicFilter::new("a/+/c").unwrap();
+        assert!(filter.matches(&Topic::new("a/b/c").unwrap()));
+        assert!(!filter.matches(&Topic::new("a/b/d").unwrap()));
+        let filter = TopicFilter::new("#").unwrap();
+        assert!(!filter.matches(&Topic::new("$SYS/broker").unwrap()));
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+
+/// Values stored by topic filter, matched against topics in time proportional to
+/// the topic depth rather than the number of filters.
+///
+/// Filters may contain `+` and `#` wildcards. As required by the MQTT spec, topics
+/// starting with `$` (e.g. `$SYS/...`) are not matched by a leading wildcard.
+pub struct TopicTree<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+struct Node<T> {
+    children: HashMap<String, Node<T>>,
+    values: Vec<T>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            children: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.values.is_empty()
+    }
+
+    fn remove(&mut self, levels: &[&str]) -> Vec<T> {
+        match levels.split_first() {
+            None => std::mem::take(&mut self.values),
+            Some((level, rest)) => {
+                let (removed, prune) = match self.children.get_mut(*level) {
+                    Some(child) => {
+                        let removed = child.remove(rest);
+                        (removed, child.is_empty())
+                    }
+                    None => (Vec::new(), false),
+                };
+                if prune {
+                    self.children.remove(*level);
+                }
+                removed
+            }
+        }
+    }
+
+    fn collect<'a>(&'a self, levels: &[&str], skip_wildcards: bool, matches: &mut Vec<&'a T>) {
+        if !skip_wildcards {
+            if let Some(child) = self.children.get("#") {
+                matches.extend(child.values.iter());
+            }
+        }
+
+        match levels.split_first() {
+            None => matches.extend(self.values.iter()),
+            Some((level, rest)) => {
+                if let Some(child) = self.children.get(*level) {
+                    child.collect(rest, false, matches);
+                }
+                if !skip_wildcards {
+                    if let Some(child) = self.children.get("+") {
+                        child.collect(rest, false, matches);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn collect_mut<'a>(
+        &'a mut self,
+        levels: &[&str],
+        skip_wildcards: bool,
+        matches: &mut Vec<&'a mut T>,
+    ) {
+        let (level, rest) = match levels.split_first() {
+            Some((level, rest)) => (Some(*level), rest),
+            None => (None, levels),
+        };
+
+        // Borrow the children matching this level at once, in the order of collect.
+        let (mut multi, mut exact, mut single) = (None, None, None);
+        for (key, child) in self.children.iter_mut() {
+            match key.as_str() {
+                "#" if !skip_wildcards => multi = Some(child),
+                "+" if !skip_wildcards && level.is_some() => single = Some(child),
+                key if Some(key) == level => exact = Some(child),
+                _ => {}
+            }
+        }
+
+        if let Some(child) = multi {
+            matches.extend(child.values.iter_mut());
+        }
+        if level.is_none() {
+            matches.extend(self.values.iter_mut());
+        }
+        if let Some(child) = exact {
+            child.collect_mut(rest, false, matches);
+        }
+        if let Some(child) = single {
+            child.collect_mut(rest, false, matches);
+        }
+    }
+
+    fn best<'a>(&'a self, levels: &[&str], skip_wildcards: bool) -> Option<&'a T> {
+        match levels.split_first() {
+            None => {
+                if let Some(value) = self.values.first() {
+                    return Some(value);
+                }
+            }
+            Some((level, rest)) => {
+                let exact = self.children.get(*level);
+                if let Some(value) = exact.and_then(|child| child.best(rest, false)) {
+                    return Some(value);
+                }
+                if !skip_wildcards {
+                    let single = self.children.get("+");
+                    if let Some(value) = single.and_then(|child| child.best(rest, false)) {
+                        return Some(value);
+                    }
+                }
+            }
+        }
+        if skip_wildcards {
+            return None;
+        }
+        self.children
+            .get("#")
+            .and_then(|child| child.values.first())
+    }
+}
+
+impl<T> Default for TopicTree<T> {
+    fn default() -> Self {
+        TopicTree {
+            root: Node::default(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> TopicTree<T> {
+    pub fn new() -> Self {
+        TopicTree::default()
+    }
+
+    /// Store value under topic filter. A filter may hold several values.
+    pub fn insert(&mut self, filter: &str, value: T) {
+        let mut node = &mut self.root;
+        for level in filter.split('/') {
+            node = node.children.entry(level.to_string()).or_default();
+        }
+        node.values.push(value);
+        self.len += 1;
+    }
+
+    /// Remove and return all values stored under topic filter.
+    pub fn remove(&mut self, filter: &str) -> Vec<T> {
+        let levels: Vec<&str> = filter.split('/').collect();
+        let removed = self.root.remove(&levels);
+        self.len -= removed.len();
+        removed
+    }
+
+    /// Return values of all filters matching topic.
+    pub fn matches(&self, topic: &str) -> Vec<&T> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let mut matches = Vec::new();
+        self.root
+            .collect(&levels, topic.starts_with('$'), &mut matches);
+        matches
+    }
+
+    /// Return values of all filters matching topic for modification.
+    pub fn matches_mut(&mut self, topic: &str) -> Vec<&mut T> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let mut matches = Vec::new();
+        self.root
+            .collect_mut(&levels, topic.starts_with('$'), &mut matches);
+        matches
+    }
+
+    /// Return value of the most specific filter matching topic. Filters are
+    /// compared level by level from the left: an exact level beats `+`, which
+    /// beats `#`. Of several values stored under that filter the first is returned.
+    pub fn best_match(&self, topic: &str) -> Option<&T> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        self.root.best(&levels, topic.starts_with('$'))
+    }
+
+    /// Return number of stored values.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(filters: &[&'static str]) -> TopicTree<&'static str> {
+        let mut tree = TopicTree::new();
+        for filter in filters {
+            tree.insert(filter, *filter);
+        }
+        tree
+    }
+
+    fn matches(tree: &TopicTree<&'static str>, topic: &str) -> Vec<&'static str> {
+        let mut matches: Vec<&'static str> = tree.matches(topic).into_iter().copied().collect();
+        matches.sort_unstable();
+        matches
+    }
+
+    #[test]
+    fn matches_exact_filter() {
+        let tree = tree(&["a/b", "a/c"]);
+        assert_eq!(matches(&tree, "a/b"), vec!["a/b"]);
+        assert!(matches(&tree, "a").is_empty());
+        assert!(matches(&tree, "a/b/c").is_empty());
+    }
+
+    #[test]
+    fn matches_single_level_wildcard() {
+        let tree = tree(&["a/+", "+/b", "a/+/c"]);
+        assert_eq!(matches(&tree, "a/b"), vec!["+/b", "a/+"]);
+        assert_eq!(matches(&tree, "a/x/c"), vec!["a/+/c"]);
+        assert!(matches(&tree, "a").is_empty());
+        assert_eq!(matches(&tree, "a/"), vec!["a/+"]);
+    }
+
+    #[test]
+    fn multi_level_wildcard_matches_parent_level() {
+        let tree = tree(&["a/#", "#"]);
+        assert_eq!(matches(&tree, "a"), vec!["#", "a/#"]);
+        assert_eq!(matches(&tree, "a/b/c"), vec!["#", "a/#"]);
+        assert_eq!(matches(&tree, "b"), vec!["#"]);
+    }
+
+    #[test]
+    fn leading_wildcard_skips_dollar_topics() {
+        let tree = tree(&["#", "+/broker", "$SYS/#", "$SYS/+"]);
+        assert_eq!(matches(&tree, "$SYS/broker"), vec!["$SYS/#", "$SYS/+"]);
+        assert_eq!(matches(&tree, "sys/broker"), vec!["#", "+/broker"]);
+    }
+
+    #[test]
+    fn best_match_prefers_most_specific_filter() {
+        let tree = tree(&["#", "a/#", "a/+", "a/b", "+/b"]);
+        assert_eq!(tree.best_match("a/b"), Some(&"a/b"));
+        assert_eq!(tree.best_match("a/c"), Some(&"a/+"));
+        assert_eq!(tree.best_match("a/c/d"), Some(&"a/#"));
+        assert_eq!(tree.best_match("a"), Some(&"a/#"));
+        assert_eq!(tree.best_match("x/b"), Some(&"+/b"));
+        assert_eq!(tree.best_match("x/c"), Some(&"#"));
+    }
+
+    #[test]
+    fn best_match_backtracks_from_dead_ends() {
+        let tree = tree(&["a/b/c", "a/+/d", "+/b/d"]);
+        assert_eq!(tree.best_match("a/b/d"), Some(&"a/+/d"));
+        assert_eq!(tree.best_match("x/b/d"), Some(&"+/b/d"));
+        assert_eq!(tree.best_match("a/b/e"), None);
+    }
+
+    #[test]
+    fn best_match_skips_dollar_topics_for_leading_wildcards() {
+        let wildcards = tree(&["#", "+/broker"]);
+        assert_eq!(wildcards.best_match("$SYS/broker"), None);
+        let sys = tree(&["#", "$SYS/#"]);
+        assert_eq!(sys.best_match("$SYS/broker"), Some(&"$SYS/#"));
+    }
+
+    #[test]
+    fn matches_mut_finds_same_values_as_matches() {
+        let mut tree = tree(&["#", "a/#", "a/+", "a/b", "+/b", "a/b/c", "$SYS/#"]);
+        for topic in ["a/b", "a", "a/b/c", "x/b", "$SYS/broker"] {
+            let expected = tree.matches(topic).into_iter().copied().collect::<Vec<_>>();
+            let found = tree.matches_mut(topic).into_iter().map(|value| *value);
+            assert_eq!(found.collect::<Vec<_>>(), expected, "topic {}", topic);
+        }
+    }
+
+    #[test]
+    fn matches_mut_modifies_values() {
+        let mut tree = TopicTree::new();
+        tree.insert("a/+", 1);
+        tree.insert("a/b", 2);
+        for value in tree.matches_mut("a/b") {
+            *value *= 10;
+        }
+        assert_eq!(tree.matches("a/c"), vec![&10]);
+        assert_eq!(tree.matches("a/b").len(), 2);
+    }
+
+    #[test]
+    fn keeps_several_values_per_filter() {
+        let mut tree = TopicTree::new();
+        tree.insert("a/b", 1);
+        tree.insert("a/b", 2);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.matches("a/b"), vec![&1, &2]);
+    }
+
+    #[test]
+    fn remove_prunes_empty_nodes() {
+        let mut tree = tree(&["a/b/c", "a/b/c", "a/x"]);
+        assert_eq!(tree.remove("a/b/c"), vec!["a/b/c", "a/b/c"]);
+        assert_eq!(tree.len(), 1);
+        assert!(!tree.root.children["a"].children.contains_key("b"));
+
+        assert_eq!(tree.remove("a/x"), vec!["a/x"]);
+        assert!(tree.is_empty());
+        assert!(tree.root.is_empty());
+    }
+
+    #[test]
+    fn remove_unknown_filter_keeps_tree() {
+        let mut tree = tree(&["a/b"]);
+        assert!(tree.remove("a/c").is_empty());
+        assert!(tree.remove("a").is_empty());
+        assert_eq!(matches(&tree, "a/b"), vec!["a/b"]);
+        assert_eq!(tree.len(), 1);
+    }
+}
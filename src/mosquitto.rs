@@ -1,21 +1,56 @@
 use mosquitto_mqtt_sys_rust as mqtt;
 
+use crate::channel::{self, OverflowPolicy};
+use crate::client_handle::ClientHandle;
+use crate::delivery::{Deliveries, DeliveryToken};
+use crate::model::ClientOption;
+use crate::model::Codec;
+use crate::model::Codecs;
+use crate::model::ConnectReason;
 use crate::model::Message;
+use crate::model::OptionValue;
+use crate::model::ProtocolVersion;
+use crate::model::QoS;
+use crate::model::SubscribeOptions;
+use crate::model::TopicFilter;
 use crate::model::Version;
-use crate::Callbacks;
+use crate::offline_queue::{OfflineQueue, OfflineQueueConfig, SharedQueue};
+use crate::property::Properties;
+use crate::proxy::ProxyConfig;
+use crate::router::Router;
+use crate::rpc::{self, Requests};
+use crate::subscription::{self, Subscriptions};
+use crate::sync::lock;
+use crate::tls::{PskConfig, SecureFile, TlsConfig, VerifyMode};
+use crate::{mqtt_pw_callback, Callbacks, ErrorHandler};
 use crate::{Error, Result};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use std::ffi::CString;
 use std::path::PathBuf;
 use std::ptr;
-use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
 use std::u8;
 
 /// Mosquitto
 pub struct Mosquitto {
     mosq: *mut mqtt::mosquitto,
+    id: String,
     version: Version,
-    callback: Option<Callbacks>,
+    protocol: Mutex<ProtocolVersion>,
+    callback: Box<Callbacks>,
+    requests: Option<Arc<Requests>>,
+    router: Option<Arc<Mutex<Router>>>,
+    subscriptions: Arc<Subscriptions>,
+    queue: SharedQueue,
+    deliveries: Arc<Deliveries>,
+    handle: ClientHandle,
+    errors: ErrorHandler,
+    tls_files: Vec<SecureFile>,
+    looping: AtomicBool,
+    threaded: AtomicBool,
 }
 
 unsafe impl std::marker::Send for Mosquitto {}
@@ -50,11 +85,42 @@ impl Mosquitto {
             return Err(Error::MosquittoNull.into());
         }
 
-        Ok(Mosquitto {
+        let subscriptions = Arc::new(Subscriptions::default());
+        let deliveries = Arc::new(Deliveries::default());
+        let queue = SharedQueue::default();
+        let mut mosquitto = Mosquitto {
             mosq,
+            id: id.to_string(),
             version: Version::obtain_version(),
-            callback: None,
-        })
+            protocol: Mutex::new(ProtocolVersion::V311),
+            callback: Box::new(Callbacks::new()),
+            requests: None,
+            router: None,
+            subscriptions: subscriptions.clone(),
+            queue: queue.clone(),
+            deliveries: deliveries.clone(),
+            handle: ClientHandle::new(
+                mosq,
+                deliveries.clone(),
+                subscriptions.clone(),
+                queue.clone(),
+            ),
+            errors: ErrorHandler::default(),
+            looping: AtomicBool::new(false),
+            threaded: AtomicBool::new(false),
+            tls_files: Vec::new(),
+        };
+        let errors = mosquitto.errors.clone();
+        let handle = ClientHandle::new(mosq, deliveries.clone(), subscriptions.clone(), queue);
+        // Nothing runs callbacks yet, so the shared state can still be set up.
+        let callbacks = &mut mosquitto.callback;
+        callbacks.on_error(errors);
+        callbacks.on_handle(handle);
+        unsafe {
+            callbacks.on_session(mosq, subscriptions);
+            callbacks.on_delivery(mosq, deliveries);
+        }
+        Ok(mosquitto)
     }
 
     /// Return libmosquitto version.
@@ -62,52 +128,299 @@ impl Mosquitto {
         &self.version
     }
 
-    /// Return mosquitto pointer.
-    pub fn mosq(&self) -> *mut mqtt::mosquitto {
+    /// # Safety
+    ///
+    /// Return mosquitto pointer. Calling libmosquitto directly bypasses the
+    /// invariants of this client: the user data, callbacks, delivery tracking and
+    /// offline queue must not be changed through it, and the pointer must not be
+    /// used after the client is dropped.
+    pub unsafe fn mosq(&self) -> *mut mqtt::mosquitto {
         self.mosq
     }
 
-    /// Initiate callback.
-    pub fn callback_init(&mut self) {
-        self.callback = Callbacks::new().into();
+    /// Initiate callback. Callbacks are initiated by new, so this is a no-op kept
+    /// for compatibility.
+    pub fn callback_init(&mut self) {}
+
+    fn callbacks(&self) -> &Callbacks {
+        &self.callback
     }
 
-    /// Sets message callback.
+    /// Sets message callback. The callback receives a handle to publish, subscribe
+    /// or unsubscribe from within the callback.
     pub fn set_message_callback<C>(&mut self, callback: C)
     where
-        C: Fn(Message),
+        C: FnMut(&ClientHandle, Message) + Send,
         C: 'static,
     {
-        if let Some(cb) = &mut self.callback {
-            unsafe {
-                cb.on_message(self.mosq, callback);
-            }
+        unsafe {
+            self.callback.on_message(self.mosq, callback);
         }
     }
 
-    /// Sets connect callback.
+    /// Return receiver of incoming messages, as an alternative to the message callback
+    /// which it replaces. At most `capacity` messages are buffered; further messages
+    /// are handled according to `policy`.
+    pub fn messages(&mut self, capacity: usize, policy: OverflowPolicy) -> Receiver<Message> {
+        let (sender, receiver) = sync_channel(capacity);
+        let forward = channel::forward_messages(sender, policy);
+        let mosq = self.mosq;
+        unsafe {
+            self.callbacks()
+                .on_message(mosq, move |_: &ClientHandle, message| forward(message));
+        }
+        receiver
+    }
+
+    /// Register handler for messages matching topic filter, which may contain
+    /// `+` and `#` wildcards. Handlers run on the network thread and receive a
+    /// handle to the client, e.g. to reply.
+    /// Messages matching no route go to the message callback.
+    pub fn add_route<T, C>(&mut self, filter: T, handler: C) -> Result<()>
+    where
+        T: AsRef<str>,
+        C: FnMut(&ClientHandle, &Message) + Send,
+        C: 'static,
+    {
+        let filter = TopicFilter::new(filter.as_ref())?;
+        lock(&self.router()).add(filter.as_str(), false, Box::new(handler));
+        Ok(())
+    }
+
+    /// Register handler for topic filter and subscribe to it.
+    /// The subscription is removed again by remove_route.
+    pub fn subscribe_route<T, C>(&mut self, filter: T, qos: QoS, handler: C) -> Result<()>
+    where
+        T: AsRef<str>,
+        C: FnMut(&ClientHandle, &Message) + Send,
+        C: 'static,
+    {
+        let filter = TopicFilter::new(filter.as_ref())?;
+        self.subscribe_qos(filter.as_str(), qos)?;
+        lock(&self.router()).add(filter.as_str(), true, Box::new(handler));
+        Ok(())
+    }
+
+    /// Publish value serialized as JSON. Return token tracking delivery of the publish.
+    #[cfg(feature = "serde")]
+    pub fn publish_json<T, P>(
+        &self,
+        topic: T,
+        value: &P,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<DeliveryToken>
+    where
+        T: AsRef<str>,
+        P: serde::Serialize,
+    {
+        let payload = serde_json::to_vec(value)?;
+        self.publish_qos(topic, &payload, qos, retain)
+    }
+
+    /// Subscribe to topic filter and pass messages to handler together with
+    /// their payload deserialized from JSON. Payloads which cannot be decoded
+    /// are reported to the error callback.
+    #[cfg(feature = "serde")]
+    pub fn subscribe_json<T, P, C>(&mut self, filter: T, qos: QoS, mut handler: C) -> Result<()>
+    where
+        T: AsRef<str>,
+        P: serde::de::DeserializeOwned,
+        C: FnMut(&ClientHandle, &Message, P) + Send,
+        C: 'static,
+    {
+        let errors = self.errors.clone();
+        self.subscribe_route(
+            filter,
+            qos,
+            move |handle: &ClientHandle, message: &Message| match serde_json::from_slice::<P>(
+                message.payload(),
+            ) {
+                Ok(value) => handler(handle, message, value),
+                Err(err) => errors.report(&Error::Decode(message.topic().to_string(), err.into())),
+            },
+        )
+    }
+
+    /// Subscribe to topic filter and pass messages to handler together with
+    /// their payload decoded by the codec selected for them. Payloads which cannot
+    /// be decoded are reported to the error callback.
+    pub fn subscribe_decoded<T, P, C>(
+        &mut self,
+        filter: T,
+        qos: QoS,
+        codecs: Codecs<P>,
+        mut handler: C,
+    ) -> Result<()>
+    where
+        T: AsRef<str>,
+        P: 'static,
+        C: FnMut(&ClientHandle, &Message, P) + Send,
+        C: 'static,
+    {
+        let errors = self.errors.clone();
+        self.subscribe_route(
+            filter,
+            qos,
+            move |handle: &ClientHandle, message: &Message| match codecs.decode(message) {
+                Ok(value) => handler(handle, message, value),
+                Err(err) => errors.report(&Error::from(err)),
+            },
+        )
+    }
+
+    /// Sets error callback, receiving errors raised while handling messages in
+    /// callbacks and panics caught in callbacks. Without it such errors are logged.
+    pub fn set_error_callback<C>(&mut self, callback: C)
+    where
+        C: FnMut(&Error) + Send,
+        C: 'static,
+    {
+        self.errors.set(Box::new(callback));
+    }
+
+    /// Remove all handlers of topic filter, unsubscribing if it was subscribed
+    /// by subscribe_route.
+    pub fn remove_route<T: AsRef<str>>(&mut self, filter: T) -> Result<()> {
+        let subscribed = match &self.router {
+            Some(router) => lock(router).remove(filter.as_ref()),
+            None => false,
+        };
+        if subscribed {
+            self.unsubscribe(filter)?;
+        }
+        Ok(())
+    }
+
+    fn router(&mut self) -> Arc<Mutex<Router>> {
+        if let Some(router) = &self.router {
+            return router.clone();
+        }
+
+        let router = Arc::new(Mutex::new(Router::default()));
+        let mosq = self.mosq;
+        unsafe {
+            self.callbacks().on_route(mosq, router.clone());
+        }
+        self.router = Some(router.clone());
+        router
+    }
+
+    /// Sets connect callback. The callback receives a handle to the client and the
+    /// decoded CONNACK code. Subscriptions are replayed after the callback returns,
+    /// skipping filters it subscribed to again.
     pub fn set_connect_callback<C>(&mut self, callback: C)
     where
-        C: Fn(i32),
+        C: FnMut(&ClientHandle, ConnectReason) + Send,
         C: 'static,
     {
-        if let Some(cb) = &mut self.callback {
-            unsafe {
-                cb.on_connect(self.mosq, callback);
-            }
+        unsafe {
+            self.callback.on_connect(self.mosq, callback);
         }
     }
 
     /// Sets disconnect callback.
     pub fn set_disconnect_callback<C>(&mut self, callback: C)
     where
-        C: Fn(i32),
+        C: FnMut(i32) + Send,
         C: 'static,
     {
-        if let Some(cb) = &mut self.callback {
-            unsafe {
-                cb.on_disconnect(self.mosq, callback);
-            }
+        unsafe {
+            self.callback.on_disconnect(self.mosq, callback);
+        }
+    }
+
+    /// Sets publish callback. The callback receives the message id of a publish
+    /// once it has been sent, or acknowledged by broker for QoS > 0.
+    pub fn set_publish_callback<C>(&mut self, callback: C)
+    where
+        C: FnMut(i32) + Send,
+        C: 'static,
+    {
+        unsafe {
+            self.callback.on_publish(self.mosq, callback);
+        }
+    }
+
+    /// Sets subscribe callback. The callback receives the message id of a
+    /// subscription and the QoS levels granted by broker.
+    pub fn set_subscribe_callback<C>(&mut self, callback: C)
+    where
+        C: FnMut(i32, &[i32]) + Send,
+        C: 'static,
+    {
+        unsafe {
+            self.callback.on_subscribe(self.mosq, callback);
+        }
+    }
+
+    /// Sets unsubscribe callback. The callback receives the message id of an
+    /// unsubscription once broker acknowledged it.
+    pub fn set_unsubscribe_callback<C>(&mut self, callback: C)
+    where
+        C: FnMut(i32) + Send,
+        C: 'static,
+    {
+        unsafe {
+            self.callback.on_unsubscribe(self.mosq, callback);
+        }
+    }
+
+    /// Sets log callback. The callback receives the log level, one of the
+    /// MOSQ_LOG_* values, and the log message of libmosquitto.
+    pub fn set_log_callback<C>(&mut self, callback: C)
+    where
+        C: FnMut(i32, &str) + Send,
+        C: 'static,
+    {
+        unsafe {
+            self.callback.on_log(self.mosq, callback);
+        }
+    }
+
+    /// Removes message callback. Messages matching no route are dropped.
+    pub fn clear_message_callback(&mut self) {
+        unsafe {
+            self.callback.clear_message(self.mosq);
+        }
+    }
+
+    /// Removes connect callback. Returns once a running call of it finished.
+    /// Delivery tracking, subscription replay and the offline queue keep working.
+    pub fn clear_connect_callback(&mut self) {
+        self.callback.clear_connect();
+    }
+
+    /// Removes disconnect callback. Returns once a running call of it finished.
+    /// Delivery tracking, subscription replay and the offline queue keep working.
+    pub fn clear_disconnect_callback(&mut self) {
+        self.callback.clear_disconnect();
+    }
+
+    /// Removes publish callback. Returns once a running call of it finished.
+    /// Delivery tracking, subscription replay and the offline queue keep working.
+    pub fn clear_publish_callback(&mut self) {
+        self.callback.clear_publish();
+    }
+
+    /// Removes subscribe callback.
+    pub fn clear_subscribe_callback(&mut self) {
+        unsafe {
+            self.callback.clear_subscribe(self.mosq);
+        }
+    }
+
+    /// Removes unsubscribe callback.
+    pub fn clear_unsubscribe_callback(&mut self) {
+        unsafe {
+            self.callback.clear_unsubscribe(self.mosq);
+        }
+    }
+
+    /// Removes log callback.
+    pub fn clear_log_callback(&mut self) {
+        unsafe {
+            self.callback.clear_log(self.mosq);
         }
     }
 
@@ -139,6 +452,141 @@ impl Mosquitto {
         Ok(())
     }
 
+    /// Sets MQTT protocol version. Must be called before connect.
+    pub fn set_protocol_version(&self, version: ProtocolVersion) -> Result<()> {
+        self.set_option(ClientOption::ProtocolVersion(version))
+    }
+
+    /// Sets client option. Must be called before connect.
+    pub fn set_option(&self, option: ClientOption) -> Result<()> {
+        info!("Mosquitto: Setting option {:?}", option);
+        match option.value() {
+            OptionValue::Int(value) => self.int_option(option.as_raw(), value)?,
+            OptionValue::String(value) => self.string_option(option.as_raw(), value)?,
+        }
+        if let ClientOption::ProtocolVersion(version) = option {
+            *lock(&self.protocol) = version;
+        }
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// Sets OpenSSL context used for TLS. ctx must point to a valid SSL_CTX,
+    /// which must outlive the client. Must be called before connect.
+    pub unsafe fn set_ssl_ctx(&self, ctx: *mut ::std::os::raw::c_void) -> Result<()> {
+        info!("Mosquitto: Setting SSL context");
+        let status = mqtt::mosquitto_void_option(self.mosq, mqtt::mosq_opt_t_MOSQ_OPT_SSL_CTX, ctx);
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoOption(status).into());
+        }
+        Ok(())
+    }
+
+    fn is_v5(&self) -> bool {
+        *lock(&self.protocol) == ProtocolVersion::V5
+    }
+
+    fn int_option(&self, option: mqtt::mosq_opt_t, value: i32) -> Result<()> {
+        let status = unsafe { mqtt::mosquitto_int_option(self.mosq, option, value) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoOption(status).into());
+        }
+        Ok(())
+    }
+
+    fn string_option(&self, option: mqtt::mosq_opt_t, value: &str) -> Result<()> {
+        let value = CString::new(value)?;
+        let status = unsafe { mqtt::mosquitto_string_option(self.mosq, option, value.as_ptr()) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoOption(status).into());
+        }
+        Ok(())
+    }
+
+    /// Sets last will, published by broker when the client disconnects unexpectedly.
+    /// Must be called before connect.
+    pub fn will_set<T: AsRef<str>>(
+        &self,
+        topic: T,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<()> {
+        info!("Mosquitto: Setting will");
+        let tpc = CString::new(topic.as_ref())?;
+        let status = unsafe {
+            mqtt::mosquitto_will_set(
+                self.mosq,
+                tpc.as_ptr(),
+                payload.len() as ::std::os::raw::c_int,
+                payload.as_ptr() as *const ::std::os::raw::c_void,
+                qos.as_raw(),
+                retain,
+            )
+        };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoWillSet(status).into());
+        }
+        Ok(())
+    }
+
+    /// Removes last will. Must be called before connect.
+    pub fn will_clear(&self) -> Result<()> {
+        info!("Mosquitto: Clearing will");
+        let status = unsafe { mqtt::mosquitto_will_clear(self.mosq) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoWillClear(status).into());
+        }
+        Ok(())
+    }
+
+    /// Sets delay between reconnect attempts of the network loop, in seconds.
+    /// With exponential backoff the delay doubles up to delay_max.
+    pub fn reconnect_delay_set(&self, delay: u32, delay_max: u32, exponential: bool) -> Result<()> {
+        info!("Mosquitto: Setting reconnect delay");
+        let status = unsafe {
+            mqtt::mosquitto_reconnect_delay_set(self.mosq, delay, delay_max, exponential)
+        };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoReconnectDelaySet(status).into());
+        }
+        Ok(())
+    }
+
+    /// Tells libmosquitto that the network loop runs in a thread started by the
+    /// application, so that it enables its internal locking. Such a client can
+    /// not be converted into a `Client`, which runs its own network thread.
+    pub fn set_threaded(&self, threaded: bool) -> Result<()> {
+        let status = unsafe { mqtt::mosquitto_threaded_set(self.mosq, threaded) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoThreadedSet(status).into());
+        }
+        self.threaded.store(threaded, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Connect to broker through a SOCKS5 proxy. Must be called before connect.
+    pub fn set_proxy(&self, proxy: &ProxyConfig) -> Result<()> {
+        info!("Mosquitto: Setting SOCKS5 proxy");
+        let host = proxy.host()?;
+        let username = proxy.username()?;
+        let password = proxy.password()?;
+        let status = unsafe {
+            mqtt::mosquitto_socks5_set(
+                self.mosq,
+                host.as_ptr(),
+                proxy.port(),
+                optional_ptr(&username),
+                optional_ptr(&password),
+            )
+        };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoSocks5Set(status).into());
+        }
+        Ok(())
+    }
+
     /// Connect to broker.
     pub fn connect(&mut self, host: &str, port: i32, keep_alive: i32) -> Result<()> {
         info!("Mosquitto: Connect to broker");
@@ -151,6 +599,46 @@ impl Mosquitto {
         Ok(())
     }
 
+    /// Connect to broker using the given local address.
+    pub fn connect_bind(
+        &mut self,
+        host: &str,
+        port: i32,
+        keep_alive: i32,
+        bind_address: &str,
+    ) -> Result<()> {
+        info!("Mosquitto: Connect to broker from {}", bind_address);
+        let hostname = CString::new(host)?;
+        let bind_address = CString::new(bind_address)?;
+        let status = unsafe {
+            mqtt::mosquitto_connect_bind(
+                self.mosq,
+                hostname.as_ptr(),
+                port,
+                keep_alive,
+                bind_address.as_ptr(),
+            )
+        };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoConnect(status).into());
+        }
+        Ok(())
+    }
+
+    /// Connect to broker without blocking. The connection completes in the network
+    /// loop, which must be started with mqtt_loop_start.
+    pub fn connect_async(&mut self, host: &str, port: i32, keep_alive: i32) -> Result<()> {
+        info!("Mosquitto: Connect to broker asynchronously");
+        let hostname = CString::new(host)?;
+        let status = unsafe {
+            mqtt::mosquitto_connect_async(self.mosq, hostname.as_ptr(), port, keep_alive)
+        };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoConnect(status).into());
+        }
+        Ok(())
+    }
+
     /// Disconnect to broker.
     pub fn disconnect(&self) -> Result<()> {
         info!("Mosquitto: Disconnect broker");
@@ -161,6 +649,26 @@ impl Mosquitto {
         Ok(())
     }
 
+    /// Disconnect from broker with an MQTT v5 reason code.
+    pub fn disconnect_with_reason(&self, reason_code: i32) -> Result<()> {
+        info!("Mosquitto: Disconnect broker with reason {}", reason_code);
+        let status = unsafe { mqtt::mosquitto_disconnect_v5(self.mosq, reason_code, ptr::null()) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoDisconnect(status).into());
+        }
+        Ok(())
+    }
+
+    /// Reconnect to broker without blocking, see connect_async.
+    pub fn reconnect_async(&self) -> Result<()> {
+        debug!("Mosquitto: Reconnect to broker asynchronously");
+        let status = unsafe { mqtt::mosquitto_reconnect_async(self.mosq) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoReconnect(status).into());
+        }
+        Ok(())
+    }
+
     /// Reconnect to broker.
     pub fn reconnect(&self) -> Result<()> {
         debug!("Mosquitto: Reconnect to broker");
@@ -171,54 +679,188 @@ impl Mosquitto {
         Ok(())
     }
 
-    /// Publish to broker.
-    pub fn publish(&self, topic: &str, payload: &[u8]) -> Result<()> {
+    /// Publish to broker. Return token tracking delivery of the publish.
+    pub fn publish<T: AsRef<str>>(&self, topic: T, payload: &[u8]) -> Result<DeliveryToken> {
+        self.publish_qos(topic, payload, QoS::AtMostOnce, false)
+    }
+
+    /// Publish to broker with given QoS. Return token tracking delivery of the
+    /// publish.
+    pub fn publish_qos<T: AsRef<str>>(
+        &self,
+        topic: T,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<DeliveryToken> {
+        self.send_publish(topic.as_ref(), payload, qos, retain, &Properties::new())
+    }
+
+    /// Publish value encoded by codec. With MQTT v5 the content type of the codec
+    /// is sent along. Return token tracking delivery of the publish.
+    pub fn publish_encoded<T, P>(
+        &self,
+        topic: T,
+        value: &P,
+        codec: &dyn Codec<P>,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<DeliveryToken>
+    where
+        T: AsRef<str>,
+    {
+        let payload = codec.encode(value)?;
+        let mut properties = Properties::new();
+        if self.is_v5() {
+            properties.add_string(
+                mqtt::mqtt5_property_MQTT_PROP_CONTENT_TYPE,
+                codec.content_type(),
+            )?;
+        }
+        self.send_publish(topic.as_ref(), &payload, qos, retain, &properties)
+    }
+
+    /// Publish through the same path as the handle lent to callbacks.
+    fn send_publish(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+        properties: &Properties,
+    ) -> Result<DeliveryToken> {
         debug!("Mosquitto: Send publish");
-        let tpc = CString::new(topic)?;
-        let status = unsafe {
-            mqtt::mosquitto_publish(
-                self.mosq,
-                ptr::null_mut(),
-                tpc.as_ptr(),
-                payload.len() as ::std::os::raw::c_int,
-                payload.as_ptr() as *const ::std::os::raw::c_void,
-                0,
-                false,
-            )
-        };
+        self.handle
+            .send_publish(topic, payload, qos, retain, properties)
+    }
 
-        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
-            return Err(Error::MosquittoPublish(status).into());
+    /// Keep messages published while disconnected in an offline queue persisted
+    /// to disk, and publish them in order after reconnect. Messages left from an
+    /// earlier run are loaded. Must be called before connect.
+    pub fn set_offline_queue(&mut self, config: OfflineQueueConfig) -> Result<()> {
+        info!("Mosquitto: Setting offline queue");
+        let queue = Arc::new(OfflineQueue::open(config)?);
+        let mosq = self.mosq;
+        unsafe {
+            self.callbacks().on_queue(mosq, queue.clone());
         }
+        *lock(&self.queue) = Some(queue);
         Ok(())
     }
 
-    /// Subscribe to broker.
-    pub fn subscribe(&self, topic: &str) -> Result<()> {
-        debug!("Mosquitto: Subscribe to broker");
-        let subscription_pattern = CString::new(topic)?;
-        let status = unsafe {
-            mqtt::mosquitto_subscribe(self.mosq, ptr::null_mut(), subscription_pattern.as_ptr(), 0)
-        };
+    /// Return number of messages waiting in the offline queue.
+    pub fn queued_messages(&self) -> usize {
+        lock(&self.queue).as_ref().map_or(0, |queue| queue.len())
+    }
 
-        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
-            return Err(Error::MosquittoSubscribe(status).into());
-        }
+    /// Subscribe to broker.
+    pub fn subscribe<T: AsRef<str>>(&self, topic: T) -> Result<()> {
+        self.subscribe_qos(topic, QoS::AtMostOnce)?;
         Ok(())
     }
 
+    /// Subscribe to broker with given QoS. Return message id of the subscription.
+    pub fn subscribe_qos<T: AsRef<str>>(&self, topic: T, qos: QoS) -> Result<i32> {
+        self.subscribe_with_options(topic, qos, SubscribeOptions::default())
+    }
+
+    /// Subscribe to broker with MQTT v5 subscription options. Return message id of
+    /// the subscription. The subscription is remembered and made again whenever the
+    /// client reconnects without a session present on broker.
+    pub fn subscribe_with_options<T: AsRef<str>>(
+        &self,
+        topic: T,
+        qos: QoS,
+        options: SubscribeOptions,
+    ) -> Result<i32> {
+        debug!("Mosquitto: Subscribe to broker");
+        let mid = unsafe { subscription::subscribe(self.mosq, topic.as_ref(), qos, options)? };
+        self.subscriptions.add(topic.as_ref(), qos, options);
+        Ok(mid)
+    }
+
     /// Unsubscribe from broker.
-    pub fn unsubscribe(&self, topic: &str) -> Result<()> {
+    pub fn unsubscribe<T: AsRef<str>>(&self, topic: T) -> Result<()> {
         debug!("Mosquitto: Unsubscribe from broker");
-        let subscription_pattern = CString::new(topic)?;
-        let status = unsafe {
-            mqtt::mosquitto_unsubscribe(self.mosq, ptr::null_mut(), subscription_pattern.as_ptr())
+        self.subscriptions.remove(topic.as_ref());
+        unsafe { subscription::unsubscribe(self.mosq, topic.as_ref()) }
+    }
+
+    /// Send request with given QoS and wait for the reply. Requires MQTT v5 and a
+    /// running network loop, e.g. started with mqtt_loop_start.
+    /// The reply is expected on the client response topic `<client id>/rpc/response`,
+    /// which is subscribed on first request. Fails if the client id contains `/`,
+    /// `+` or `#`.
+    pub fn request<T: AsRef<str>>(
+        &mut self,
+        topic: T,
+        payload: &[u8],
+        qos: QoS,
+        timeout: Duration,
+    ) -> Result<Message> {
+        debug!("Mosquitto: Send request");
+        let requests = match &self.requests {
+            Some(requests) => requests.clone(),
+            None => {
+                let requests = Arc::new(Requests::new(&self.id)?);
+                let mosq = self.mosq;
+                unsafe {
+                    self.callbacks().on_reply(mosq, requests.clone());
+                }
+                self.requests = Some(requests.clone());
+                requests
+            }
         };
 
-        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
-            return Err(Error::MosquittoUnsubscribe(status).into());
+        if requests.needs_subscribe() {
+            self.subscribe(requests.response_topic())?;
+            requests.set_subscribed();
+        }
+
+        let (correlation_data, receiver) = requests.register();
+        let published = unsafe {
+            rpc::publish_with_response(
+                self.mosq,
+                &self.deliveries,
+                topic.as_ref(),
+                payload,
+                qos,
+                Some(requests.response_topic()),
+                Some(&correlation_data),
+            )
+        };
+        if let Err(err) = published {
+            requests.cancel(&correlation_data);
+            return Err(err);
+        }
+
+        match receiver.recv_timeout(timeout) {
+            Ok(reply) => Ok(reply),
+            Err(_) => {
+                requests.cancel(&correlation_data);
+                Err(Error::RequestTimeout.into())
+            }
+        }
+    }
+
+    /// Reply to a request with given QoS using its response topic and correlation
+    /// data. Return token tracking delivery of the reply.
+    pub fn respond(&self, request: &Message, payload: &[u8], qos: QoS) -> Result<DeliveryToken> {
+        debug!("Mosquitto: Send response");
+        let response_topic = request
+            .response_topic()
+            .ok_or(Error::MissingResponseTopic)?;
+        unsafe {
+            rpc::publish_with_response(
+                self.mosq,
+                &self.deliveries,
+                response_topic,
+                payload,
+                qos,
+                None,
+                request.correlation_data(),
+            )
         }
-        Ok(())
     }
 
     /// MqttLoop
@@ -232,13 +874,48 @@ impl Mosquitto {
     }
 
     /// MqttLoopStart. Will create an seperate thread to be running on.
-    /// Need to support pthread.
+    /// Need to support pthread. `Client::new` starts the thread itself unless it
+    /// is already running.
     pub fn mqtt_loop_start(&self) -> Result<()> {
         debug!("Mosquitto: loop_start");
         let status = unsafe { mqtt::mosquitto_loop_start(self.mosq) };
         if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
             return Err(Error::MosquittoMqttLoopStart(status).into());
         }
+        self.looping.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Return true while the network thread started by mqtt_loop_start runs.
+    pub fn is_loop_running(&self) -> bool {
+        self.looping.load(Ordering::SeqCst)
+    }
+
+    /// Return true if the application runs the network loop, see set_threaded.
+    pub fn is_threaded(&self) -> bool {
+        self.threaded.load(Ordering::SeqCst)
+    }
+
+    /// MqttLoopStop. Stops the network thread started by mqtt_loop_start. Unless
+    /// forced, the thread only stops after disconnect.
+    pub fn mqtt_loop_stop(&self, force: bool) -> Result<()> {
+        debug!("Mosquitto: loop_stop");
+        let status = unsafe { mqtt::mosquitto_loop_stop(self.mosq, force) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoMqttLoopStop(status).into());
+        }
+        self.looping.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// MqttLoopForever. Runs the network loop in the calling thread until
+    /// disconnect, reconnecting when the connection is lost.
+    pub fn mqtt_loop_forever(&self, timeout: i32, maxpackets: i32) -> Result<()> {
+        debug!("Mosquitto: loop_forever");
+        let status = unsafe { mqtt::mosquitto_loop_forever(self.mosq, timeout, maxpackets) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoMqttLoop(status).into());
+        }
         Ok(())
     }
 
@@ -247,6 +924,15 @@ impl Mosquitto {
         unsafe { mqtt::mosquitto_want_write(self.mosq) }
     }
 
+    /// Mqtt_loop_read. Reads incoming data when driving the socket with an external poller.
+    pub fn loop_read(&self, max_packets: i32) -> Result<()> {
+        let status = unsafe { mqtt::mosquitto_loop_read(self.mosq, max_packets) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoLoopRead(status).into());
+        }
+        Ok(())
+    }
+
     /// Mqtt_loop_write.
     pub fn loop_write(&self, max_packets: i32) -> Result<()> {
         let status = unsafe { mqtt::mosquitto_loop_write(self.mosq, max_packets) };
@@ -265,6 +951,13 @@ impl Mosquitto {
         Ok(())
     }
 
+    /// Fail publishes in flight when the connection was closed without the
+    /// disconnect callback.
+    #[cfg(all(feature = "tokio", unix))]
+    pub(crate) fn fail_deliveries(&self) {
+        self.deliveries.fail_all();
+    }
+
     /// Return socket.
     pub fn socket(&self) -> Result<i32> {
         let status = unsafe { mqtt::mosquitto_socket(self.mosq) };
@@ -276,21 +969,130 @@ impl Mosquitto {
 
     /// Setup TLS encryption.
     pub fn tls_set_using_ca_file(&self, ca_file: &PathBuf) -> Result<()> {
-        info!("Mosquitto: Setup TLS");
+        self.apply_tls(&TlsConfig::with_ca_file(ca_file), false)
+    }
+
+    /// Setup TLS encryption, optionally with a client certificate for mutual TLS.
+    /// Must be called before connect.
+    pub fn tls_set(&mut self, mut config: TlsConfig) -> Result<()> {
+        config.check()?;
+        let tls_files = config.write_pem_files()?;
+        self.tls_files.extend(tls_files);
+
+        let key_password = config.take_key_password();
+        let use_password = key_password.is_some();
+        if let Some(key_password) = key_password {
+            let mosq = self.mosq;
+            unsafe {
+                self.callbacks().on_key_password(mosq, key_password);
+            }
+        }
+        self.apply_tls(&config, use_password)
+    }
+
+    /// Sets ALPN protocol announced during the TLS handshake.
+    /// The host given to connect is used as SNI server name.
+    pub fn set_tls_alpn(&self, protocol: &str) -> Result<()> {
+        self.set_option(ClientOption::TlsAlpn(protocol.to_string()))
+    }
+
+    /// Setup TLS using a pre-shared key instead of certificates.
+    /// Must be called before connect and cannot be combined with tls_set.
+    pub fn tls_psk_set(&self, config: &PskConfig) -> Result<()> {
+        info!("Mosquitto: Setup TLS-PSK");
+        let psk = config.psk()?;
+        let identity = config.identity()?;
+        let ciphers = config.cipher_list()?;
         let status = unsafe {
-            let cert = CString::new(ca_file.to_str().unwrap()).unwrap();
-            mqtt::mosquitto_tls_set(
+            mqtt::mosquitto_tls_psk_set(
                 self.mosq,
-                cert.as_ptr(),
-                ptr::null() as *const ::std::os::raw::c_char,
-                ptr::null() as *const ::std::os::raw::c_char,
-                ptr::null() as *const ::std::os::raw::c_char,
-                None,
+                psk.as_ptr(),
+                identity.as_ptr(),
+                optional_ptr(&ciphers),
             )
         };
         if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
-            return Err(Error::MosquittoTlsSet(status).into());
+            return Err(Error::MosquittoTlsPskSet(status).into());
         }
         Ok(())
     }
+
+    fn apply_tls(&self, config: &TlsConfig, use_password: bool) -> Result<()> {
+        info!("Mosquitto: Setup TLS");
+        config.check()?;
+        let ca_file = config.ca_file()?;
+        let ca_path = config.ca_path()?;
+        let cert_file = config.cert_file()?;
+        let key_file = config.key_file()?;
+        let has_ca = ca_file.is_some() || ca_path.is_some();
+
+        // Options are only applied to the client once the config was validated.
+        if config.uses_os_certs() {
+            self.set_option(ClientOption::TlsUseOsCerts(true))?;
+        }
+
+        // libmosquitto rejects mosquitto_tls_set without a CA, the OS trust store
+        // alone is enabled by the option above.
+        if has_ca || !config.uses_os_certs() {
+            let pw_callback = if use_password {
+                Some(mqtt_pw_callback as _)
+            } else {
+                None
+            };
+
+            let status = unsafe {
+                mqtt::mosquitto_tls_set(
+                    self.mosq,
+                    optional_ptr(&ca_file),
+                    optional_ptr(&ca_path),
+                    optional_ptr(&cert_file),
+                    optional_ptr(&key_file),
+                    pw_callback,
+                )
+            };
+            if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+                return Err(Error::MosquittoTlsSet(status).into());
+            }
+        }
+
+        if config.has_options() {
+            let verify_mode = config.get_verify_mode();
+            if verify_mode == VerifyMode::None {
+                warn!("Mosquitto: TLS broker certificate verification is disabled");
+            }
+            let tls_version = config.tls_version()?;
+            let ciphers = config.cipher_list()?;
+            let status = unsafe {
+                mqtt::mosquitto_tls_opts_set(
+                    self.mosq,
+                    verify_mode.as_raw(),
+                    optional_ptr(&tls_version),
+                    optional_ptr(&ciphers),
+                )
+            };
+            if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+                return Err(Error::MosquittoTlsOptsSet(status).into());
+            }
+        }
+
+        if let Some(protocol) = config.alpn_protocol() {
+            self.set_tls_alpn(protocol)?;
+        }
+
+        if config.is_insecure() {
+            warn!("Mosquitto: TLS hostname verification is disabled, do not use in production");
+        }
+        let status = unsafe { mqtt::mosquitto_tls_insecure_set(self.mosq, config.is_insecure()) };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoTlsInsecureSet(status).into());
+        }
+        Ok(())
+    }
+}
+
+fn optional_ptr(value: &Option<CString>) -> *const ::std::os::raw::c_char {
+    match value {
+        Some(value) => value.as_ptr(),
+        None => ptr::null(),
+    }
 }
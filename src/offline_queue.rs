@@ -0,0 +1,609 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+
+use crate::delivery::DeliveryToken;
+use crate::model::QoS;
+use crate::sync::lock;
+use crate::{Error, Result};
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default bound of the queued topics and payloads.
+const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+const RECORD_PUBLISH: u8 = b'P';
+const RECORD_ACK: u8 = b'A';
+
+/// Store-and-forward queue for messages published while disconnected.
+///
+/// Messages are appended to a local file and published in order after the
+/// client reconnected. An entry is removed once it was sent for QoS 0, or
+/// acknowledged by broker for QoS > 0. When a bound is exceeded the oldest
+/// messages are dropped.
+#[derive(Debug, Clone)]
+pub struct OfflineQueueConfig {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age: Option<Duration>,
+}
+
+impl OfflineQueueConfig {
+    /// Persist the queue to file at path.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        OfflineQueueConfig {
+            path: path.into(),
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_age: None,
+        }
+    }
+
+    /// Bound the size of queued topics and payloads. Defaults to 16 MiB.
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Drop messages queued longer than max_age instead of publishing them.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+}
+
+struct Entry {
+    seq: u64,
+    timestamp: u64,
+    qos: QoS,
+    retain: bool,
+    topic: String,
+    payload: Vec<u8>,
+}
+
+impl Entry {
+    fn size(&self) -> u64 {
+        (self.topic.len() + self.payload.len()) as u64
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut record = Vec::with_capacity(27 + self.topic.len() + self.payload.len());
+        record.push(RECORD_PUBLISH);
+        record.extend_from_slice(&self.seq.to_le_bytes());
+        record.extend_from_slice(&self.timestamp.to_le_bytes());
+        record.push(self.qos.as_raw() as u8);
+        record.push(self.retain as u8);
+        record.extend_from_slice(&(self.topic.len() as u32).to_le_bytes());
+        record.extend_from_slice(self.topic.as_bytes());
+        record.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&self.payload);
+        record
+    }
+}
+
+struct Store {
+    file: File,
+    file_len: u64,
+    entries: VecDeque<Entry>,
+    // Tokens of messages queued by this run, by sequence number.
+    tokens: HashMap<u64, DeliveryToken>,
+    bytes: u64,
+    next_seq: u64,
+    connected: bool,
+}
+
+/// Offline queue set on a client, shared with its handles.
+pub(crate) type SharedQueue = Arc<Mutex<Option<Arc<OfflineQueue>>>>;
+
+/// Offline queue of a client, backed by an append-only file.
+pub(crate) struct OfflineQueue {
+    config: OfflineQueueConfig,
+    store: Mutex<Store>,
+    // Message id of flushed entries waiting for acknowledgement. Kept across
+    // reconnects, as libmosquitto resends QoS > 0 messages by itself.
+    inflight: Mutex<HashMap<i32, u64>>,
+}
+
+impl OfflineQueue {
+    /// Open queue file, loading messages left from an earlier run.
+    pub fn open(config: OfflineQueueConfig) -> Result<Self> {
+        let entries = load(&config.path)?;
+        let next_seq = entries.back().map_or(0, |entry| entry.seq + 1);
+        let bytes = entries.iter().map(Entry::size).sum();
+        let file = rewrite(&config.path, &entries)?;
+        let file_len = file.metadata()?.len();
+        info!(
+            "Opened offline queue {} with {} messages",
+            config.path.display(),
+            entries.len()
+        );
+
+        let queue = OfflineQueue {
+            config,
+            store: Mutex::new(Store {
+                file,
+                file_len,
+                entries,
+                tokens: HashMap::new(),
+                bytes,
+                next_seq,
+                connected: false,
+            }),
+            inflight: Mutex::new(HashMap::new()),
+        };
+        queue.expire(&mut lock(&queue.store));
+        Ok(queue)
+    }
+
+    /// Return number of queued messages.
+    pub fn len(&self) -> usize {
+        lock(&self.store).entries.len()
+    }
+
+    /// Queue message if client is disconnected. Return its token if it was queued.
+    pub fn enqueue(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<Option<DeliveryToken>> {
+        let mut store = lock(&self.store);
+        if store.connected {
+            return Ok(None);
+        }
+
+        let entry = Entry {
+            seq: store.next_seq,
+            timestamp: now(),
+            qos,
+            retain,
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+        };
+        if entry.size() > self.config.max_bytes {
+            warn!("Message on {} exceeds offline queue size", topic);
+            let token = DeliveryToken::new(0);
+            token.dropped();
+            return Ok(Some(token));
+        }
+
+        let record = entry.encode();
+        store.file.write_all(&record)?;
+        store.file.sync_data()?;
+        store.file_len += record.len() as u64;
+        store.next_seq += 1;
+        store.bytes += entry.size();
+        let token = DeliveryToken::new(0);
+        store.tokens.insert(entry.seq, token.clone());
+        store.entries.push_back(entry);
+        debug!("Queued message on {} while offline", topic);
+
+        while store.bytes > self.config.max_bytes {
+            let seq = match store.entries.front() {
+                Some(entry) => {
+                    warn!("Offline queue full, dropping message on {}", entry.topic);
+                    entry.seq
+                }
+                None => break,
+            };
+            self.remove(&mut store, seq);
+        }
+        Ok(Some(token))
+    }
+
+    /// # Safety
+    ///
+    /// Publish queued messages in order, skipping those already handed to
+    /// libmosquitto. Must be called from the connect callback.
+    ///
+    /// Messages rejected by libmosquitto are dropped. If the connection is lost
+    /// meanwhile, the rest stays queued for the next connect, except a QoS 1 or 2
+    /// message which libmosquitto already took over.
+    pub unsafe fn flush(&self, mosq: *mut mqtt::mosquitto) {
+        let mut store = lock(&self.store);
+        self.expire(&mut store);
+        let mut inflight = lock(&self.inflight);
+        let handed_over: HashSet<u64> = inflight.values().copied().collect();
+
+        let pending = store
+            .entries
+            .iter()
+            .filter(|entry| !handed_over.contains(&entry.seq))
+            .count();
+        if pending > 0 {
+            info!("Flushing {} offline messages", pending);
+        }
+        let mut rejected = Vec::new();
+        let mut connection_lost = false;
+        for entry in store.entries.iter() {
+            if handed_over.contains(&entry.seq) {
+                continue;
+            }
+            let (status, mid) = publish(mosq, entry);
+            if status == mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+                inflight.insert(mid, entry.seq);
+            } else if status == mqtt::mosq_err_t_MOSQ_ERR_NO_CONN {
+                warn!("Connection lost while flushing offline queue");
+                // libmosquitto keeps QoS 1 and 2 messages and sends them after
+                // reconnect, only QoS 0 messages are published again.
+                if entry.qos != QoS::AtMostOnce {
+                    inflight.insert(mid, entry.seq);
+                }
+                connection_lost = true;
+                break;
+            } else {
+                error!(
+                    "Dropping offline message on {}: {}",
+                    entry.topic,
+                    Error::MosquittoPublish(status)
+                );
+                rejected.push(entry.seq);
+            }
+        }
+        drop(inflight);
+
+        for seq in rejected {
+            self.remove(&mut store, seq);
+        }
+        if !connection_lost {
+            store.connected = true;
+        }
+    }
+
+    /// Queue messages again from now on.
+    pub fn disconnected(&self) {
+        let mut store = lock(&self.store);
+        store.connected = false;
+
+        // libmosquitto discards QoS 0 messages which were not sent yet, so these
+        // are published again on the next flush.
+        let qos0: HashSet<u64> = store
+            .entries
+            .iter()
+            .filter(|entry| entry.qos == QoS::AtMostOnce)
+            .map(|entry| entry.seq)
+            .collect();
+        lock(&self.inflight).retain(|_, seq| !qos0.contains(seq));
+    }
+
+    /// Remove flushed message once it was acknowledged. Return false if mid does
+    /// not belong to a flushed message.
+    pub fn acknowledge(&self, mid: i32) -> bool {
+        let seq = match lock(&self.inflight).remove(&mid) {
+            Some(seq) => seq,
+            None => return false,
+        };
+        let mut store = lock(&self.store);
+        if let Some(token) = store.tokens.remove(&seq) {
+            token.delivered();
+        }
+        self.remove(&mut store, seq);
+        true
+    }
+
+    fn expire(&self, store: &mut Store) {
+        let max_age = match self.config.max_age {
+            Some(max_age) => max_age.as_secs(),
+            None => return,
+        };
+        let now = now();
+        while let Some(entry) = store.entries.front() {
+            if now.saturating_sub(entry.timestamp) <= max_age {
+                break;
+            }
+            warn!("Dropping expired offline message on {}", entry.topic);
+            let seq = entry.seq;
+            self.remove(store, seq);
+        }
+    }
+
+    fn remove(&self, store: &mut Store, seq: u64) {
+        let index = match store.entries.iter().position(|entry| entry.seq == seq) {
+            Some(index) => index,
+            None => return,
+        };
+        if let Some(entry) = store.entries.remove(index) {
+            store.bytes -= entry.size();
+        }
+        if let Some(token) = store.tokens.remove(&seq) {
+            token.dropped();
+        }
+        lock(&self.inflight).retain(|_, inflight_seq| *inflight_seq != seq);
+
+        if let Err(err) = self.record_removal(store, seq) {
+            error!(
+                "Could not update offline queue {}: {}",
+                self.config.path.display(),
+                err
+            );
+        }
+    }
+
+    fn record_removal(&self, store: &mut Store, seq: u64) -> Result<()> {
+        if store.entries.is_empty() {
+            store.file.set_len(0)?;
+            store.file_len = 0;
+            return Ok(());
+        }
+        if store.file_len > 2 * self.config.max_bytes {
+            store.file = rewrite(&self.config.path, &store.entries)?;
+            store.file_len = store.file.metadata()?.len();
+            return Ok(());
+        }
+
+        let mut record = vec![RECORD_ACK];
+        record.extend_from_slice(&seq.to_le_bytes());
+        store.file.write_all(&record)?;
+        store.file_len += record.len() as u64;
+        Ok(())
+    }
+}
+
+/// Return status and message id of the publish.
+unsafe fn publish(mosq: *mut mqtt::mosquitto, entry: &Entry) -> (i32, i32) {
+    let topic = match CString::new(entry.topic.as_str()) {
+        Ok(topic) => topic,
+        Err(_) => return (mqtt::mosq_err_t_MOSQ_ERR_INVAL, 0),
+    };
+    let mut mid: ::std::os::raw::c_int = 0;
+    let status = mqtt::mosquitto_publish(
+        mosq,
+        &mut mid,
+        topic.as_ptr(),
+        entry.payload.len() as ::std::os::raw::c_int,
+        entry.payload.as_ptr() as *const ::std::os::raw::c_void,
+        entry.qos.as_raw(),
+        entry.retain,
+    );
+    (status, mid)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Read queue file, replaying acknowledgements. A truncated last record, e.g.
+/// after a power loss, is ignored.
+fn load(path: &Path) -> Result<VecDeque<Entry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(VecDeque::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut reader = BufReader::new(file);
+    let mut entries: VecDeque<Entry> = VecDeque::new();
+
+    loop {
+        let mut kind = [0u8; 1];
+        if reader.read_exact(&mut kind).is_err() {
+            break;
+        }
+        match kind[0] {
+            RECORD_PUBLISH => match read_entry(&mut reader) {
+                Some(entry) => entries.push_back(entry),
+                None => break,
+            },
+            RECORD_ACK => match read_u64(&mut reader) {
+                Some(seq) => entries.retain(|entry| entry.seq != seq),
+                None => break,
+            },
+            kind => {
+                warn!(
+                    "Unknown record {} in {}, ignoring rest",
+                    kind,
+                    path.display()
+                );
+                break;
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn read_entry<R: Read>(reader: &mut R) -> Option<Entry> {
+    let seq = read_u64(reader)?;
+    let timestamp = read_u64(reader)?;
+    let mut flags = [0u8; 2];
+    reader.read_exact(&mut flags).ok()?;
+    let qos = match flags[0] {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    };
+    let topic = String::from_utf8(read_bytes(reader)?).ok()?;
+    let payload = read_bytes(reader)?;
+    Some(Entry {
+        seq,
+        timestamp,
+        qos,
+        retain: flags[1] != 0,
+        topic,
+        payload,
+    })
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).ok()?;
+    Some(u64::from_le_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).ok()?;
+    let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// Replace queue file by one holding only the given entries and return it
+/// opened for appending.
+fn rewrite(path: &Path, entries: &VecDeque<Entry>) -> Result<File> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut tmp = File::create(&tmp_path)?;
+    for entry in entries {
+        tmp.write_all(&entry.encode())?;
+    }
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(OpenOptions::new().append(true).open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "mosquitto-mqtt-queue-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn entry(seq: u64, topic: &str, payload: &[u8]) -> Entry {
+        Entry {
+            seq,
+            timestamp: 1_700_000_000 + seq,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn ack(seq: u64) -> Vec<u8> {
+        let mut record = vec![RECORD_ACK];
+        record.extend_from_slice(&seq.to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let original = entry(7, "a/b", b"payload");
+        let record = original.encode();
+        assert_eq!(record[0], RECORD_PUBLISH);
+        assert_eq!(record.len(), 27 + 3 + 7);
+
+        let decoded = read_entry(&mut &record[1..]).unwrap();
+        assert_eq!(decoded.seq, 7);
+        assert_eq!(decoded.timestamp, original.timestamp);
+        assert_eq!(decoded.qos, QoS::AtLeastOnce);
+        assert!(!decoded.retain);
+        assert_eq!(decoded.topic, "a/b");
+        assert_eq!(decoded.payload, b"payload");
+    }
+
+    #[test]
+    fn load_replays_acknowledgements() {
+        let path = temp_path("ack");
+        let mut data = entry(0, "a", b"0").encode();
+        data.extend(entry(1, "b", b"1").encode());
+        data.extend(ack(0));
+        data.extend(entry(2, "c", b"2").encode());
+        fs::write(&path, data).unwrap();
+
+        let entries = load(&path).unwrap();
+        let seqs: Vec<u64> = entries.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, vec![1, 2]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_ignores_truncated_tail() {
+        let path = temp_path("truncated");
+        let mut data = entry(0, "a", b"0").encode();
+        let partial = entry(1, "b", b"payload").encode();
+        data.extend_from_slice(&partial[..partial.len() - 3]);
+        fs::write(&path, &data).unwrap();
+        let entries = load(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].topic, "a");
+
+        data.truncate(data.len() - partial.len() + 3);
+        data.extend_from_slice(&ack(0)[..4]);
+        fs::write(&path, &data).unwrap();
+        assert_eq!(load(&path).unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_stops_at_unknown_record() {
+        let path = temp_path("unknown");
+        let mut data = entry(0, "a", b"0").encode();
+        data.push(b'X');
+        data.extend(entry(1, "b", b"1").encode());
+        fs::write(&path, data).unwrap();
+        assert_eq!(load(&path).unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_missing_file_is_empty() {
+        let path = temp_path("missing");
+        assert!(load(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn queued_messages_survive_reopen() {
+        let path = temp_path("reopen");
+        let queue = OfflineQueue::open(OfflineQueueConfig::new(&path)).unwrap();
+        let token = queue
+            .enqueue("a/b", b"payload", QoS::AtLeastOnce, false)
+            .unwrap()
+            .unwrap();
+        assert!(!token.is_complete());
+        queue
+            .enqueue("a/c", b"payload", QoS::AtMostOnce, true)
+            .unwrap();
+        assert_eq!(queue.len(), 2);
+        drop(queue);
+
+        let queue = OfflineQueue::open(OfflineQueueConfig::new(&path)).unwrap();
+        assert_eq!(queue.len(), 2);
+        let store = lock(&queue.store);
+        assert_eq!(store.next_seq, 2);
+        assert_eq!(store.entries[1].topic, "a/c");
+        assert!(store.entries[1].retain);
+        drop(store);
+        drop(queue);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn full_queue_drops_oldest_message() {
+        let path = temp_path("full");
+        let config = OfflineQueueConfig::new(&path).max_bytes(8);
+        let queue = OfflineQueue::open(config).unwrap();
+        let first = queue
+            .enqueue("a", b"1234", QoS::AtLeastOnce, false)
+            .unwrap()
+            .unwrap();
+        queue
+            .enqueue("b", b"1234", QoS::AtLeastOnce, false)
+            .unwrap();
+        assert_eq!(queue.len(), 1);
+        assert!(first.try_result().unwrap().is_err());
+
+        let oversized = queue
+            .enqueue("c", b"12345678", QoS::AtLeastOnce, false)
+            .unwrap()
+            .unwrap();
+        assert!(oversized.try_result().unwrap().is_err());
+        assert_eq!(queue.len(), 1);
+        drop(queue);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+
+use crate::{Error, Result};
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+/// Owned list of MQTT v5 properties.
+pub(crate) struct Properties {
+    list: *mut mqtt::mosquitto_property,
+}
+
+impl Drop for Properties {
+    fn drop(&mut self) {
+        unsafe { mqtt::mosquitto_property_free_all(&mut self.list) }
+    }
+}
+
+impl Properties {
+    pub fn new() -> Self {
+        Properties {
+            list: ptr::null_mut(),
+        }
+    }
+
+    /// Return property list pointer, null if empty.
+    pub fn as_ptr(&self) -> *const mqtt::mosquitto_property {
+        self.list
+    }
+
+    /// Add string property.
+    pub fn add_string(&mut self, identifier: mqtt::mqtt5_property, value: &str) -> Result<()> {
+        let value = CString::new(value)?;
+        let status = unsafe {
+            mqtt::mosquitto_property_add_string(&mut self.list, identifier as i32, value.as_ptr())
+        };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoProperty(status).into());
+        }
+        Ok(())
+    }
+
+    /// Add binary property.
+    pub fn add_binary(&mut self, identifier: mqtt::mqtt5_property, value: &[u8]) -> Result<()> {
+        if value.len() > u16::MAX as usize {
+            return Err(Error::MosquittoProperty(mqtt::mosq_err_t_MOSQ_ERR_INVAL).into());
+        }
+        let status = unsafe {
+            mqtt::mosquitto_property_add_binary(
+                &mut self.list,
+                identifier as i32,
+                value.as_ptr() as *const ::std::os::raw::c_void,
+                value.len() as u16,
+            )
+        };
+        if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+            return Err(Error::MosquittoProperty(status).into());
+        }
+        Ok(())
+    }
+}
+
+/// # Safety
+///
+/// Read string property from a property list owned by libmosquitto.
+pub(crate) unsafe fn read_string(
+    props: *const mqtt::mosquitto_property,
+    identifier: mqtt::mqtt5_property,
+) -> Option<String> {
+    if props.is_null() {
+        return None;
+    }
+
+    let mut value: *mut ::std::os::raw::c_char = ptr::null_mut();
+    mqtt::mosquitto_property_read_string(props, identifier as i32, &mut value, false);
+    if value.is_null() {
+        return None;
+    }
+
+    let result = CStr::from_ptr(value).to_string_lossy().into_owned();
+    libc::free(value as *mut libc::c_void);
+    Some(result)
+}
+
+/// # Safety
+///
+/// Read binary property from a property list owned by libmosquitto.
+pub(crate) unsafe fn read_binary(
+    props: *const mqtt::mosquitto_property,
+    identifier: mqtt::mqtt5_property,
+) -> Option<Vec<u8>> {
+    if props.is_null() {
+        return None;
+    }
+
+    let mut value: *mut ::std::os::raw::c_void = ptr::null_mut();
+    let mut len: u16 = 0;
+    mqtt::mosquitto_property_read_binary(props, identifier as i32, &mut value, &mut len, false);
+    if value.is_null() {
+        return None;
+    }
+
+    let result = std::slice::from_raw_parts(value as *const u8, len as usize).to_vec();
+    libc::free(value);
+    Some(result)
+}
@@ -0,0 +1,62 @@
+use crate::Result;
+use std::ffi::CString;
+
+/// SOCKS5 proxy used to reach the broker.
+#[derive(Clone)]
+pub struct ProxyConfig {
+    host: String,
+    port: i32,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl std::fmt::Debug for ProxyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ProxyConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl ProxyConfig {
+    pub fn new(host: &str, port: i32) -> Self {
+        ProxyConfig {
+            host: host.to_string(),
+            port,
+            username: None,
+            password: None,
+        }
+    }
+
+    /// Authenticate to the proxy with username and password.
+    pub fn credentials(mut self, username: &str, password: &str) -> Self {
+        self.username = Some(username.to_string());
+        self.password = Some(password.to_string());
+        self
+    }
+
+    pub(crate) fn host(&self) -> Result<CString> {
+        Ok(CString::new(self.host.as_str())?)
+    }
+
+    pub(crate) fn port(&self) -> i32 {
+        self.port
+    }
+
+    pub(crate) fn username(&self) -> Result<Option<CString>> {
+        match &self.username {
+            Some(username) => Ok(Some(CString::new(username.as_str())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn password(&self) -> Result<Option<CString>> {
+        match &self.password {
+            Some(password) => Ok(Some(CString::new(password.as_str())?)),
+            None => Ok(None),
+        }
+    }
+}
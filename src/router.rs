@@ -0,0 +1,55 @@
+use crate::client_handle::ClientHandle;
+use crate::model::{Message, TopicTree};
+use log::debug;
+
+type Handler = Box<dyn FnMut(&ClientHandle, &Message) + Send>;
+
+struct Route {
+    subscribed: bool,
+    handler: Handler,
+}
+
+/// Dispatches messages to handlers registered per topic filter.
+#[derive(Default)]
+pub(crate) struct Router {
+    routes: TopicTree<Route>,
+}
+
+impl Router {
+    /// Register handler for topic filter. `subscribed` marks filters the client
+    /// subscribed to on behalf of the route.
+    pub fn add(&mut self, filter: &str, subscribed: bool, handler: Handler) {
+        self.routes.insert(
+            filter,
+            Route {
+                subscribed,
+                handler,
+            },
+        );
+    }
+
+    /// Remove all handlers of topic filter.
+    /// Return true if the client subscribed to the filter on behalf of a removed route.
+    pub fn remove(&mut self, filter: &str) -> bool {
+        self.routes
+            .remove(filter)
+            .iter()
+            .any(|route| route.subscribed)
+    }
+
+    /// Call every handler whose filter matches the message topic.
+    /// Return false if no handler matched.
+    pub fn dispatch(&mut self, handle: &ClientHandle, message: &Message) -> bool {
+        let routes = self.routes.matches_mut(message.topic());
+        debug!(
+            "Route message on {} to {} handlers",
+            message.topic(),
+            routes.len()
+        );
+        let matched = !routes.is_empty();
+        for route in routes {
+            (route.handler)(handle, message);
+        }
+        matched
+    }
+}
@@ -0,0 +1,197 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+
+use crate::delivery::{Deliveries, DeliveryToken};
+use crate::model::{Message, QoS, Topic};
+use crate::property::Properties;
+use crate::sync::lock;
+use crate::{Error, Result};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Outstanding requests waiting for a reply on the client response topic.
+pub(crate) struct Requests {
+    response_topic: String,
+    prefix: String,
+    next_id: AtomicU64,
+    subscribed: AtomicBool,
+    pending: Mutex<HashMap<Vec<u8>, Sender<Message>>>,
+}
+
+impl Requests {
+    /// Fails if client_id is not usable as a single topic level.
+    pub fn new(client_id: &str) -> Result<Self> {
+        if client_id.contains('/') || Topic::new(client_id).is_err() {
+            return Err(Error::InvalidClientId(client_id.to_string()).into());
+        }
+
+        Ok(Requests {
+            response_topic: format!("{}/rpc/response", client_id),
+            // The nonce keeps late replies from matching requests of a later run.
+            prefix: format!("{}:{:x}", client_id, nonce()),
+            next_id: AtomicU64::new(0),
+            subscribed: AtomicBool::new(false),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Return response topic of this client.
+    pub fn response_topic(&self) -> &str {
+        &self.response_topic
+    }
+
+    /// Return true until the response topic was subscribed successfully.
+    pub fn needs_subscribe(&self) -> bool {
+        !self.subscribed.load(Ordering::SeqCst)
+    }
+
+    /// Remember that the response topic was subscribed.
+    pub fn set_subscribed(&self) {
+        self.subscribed.store(true, Ordering::SeqCst);
+    }
+
+    /// Register a new request and return its correlation data.
+    pub fn register(&self) -> (Vec<u8>, Receiver<Message>) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let correlation_data = format!("{}:{}", self.prefix, id).into_bytes();
+        let (sender, receiver) = channel();
+        lock(&self.pending).insert(correlation_data.clone(), sender);
+        (correlation_data, receiver)
+    }
+
+    /// Forget a request which will not be waited on anymore.
+    pub fn cancel(&self, correlation_data: &[u8]) {
+        lock(&self.pending).remove(correlation_data);
+    }
+
+    /// Hand reply over to the waiting request.
+    /// Return the message back if it is not a reply to a pending request.
+    pub fn complete(&self, message: Message) -> Option<Message> {
+        if message.topic() != self.response_topic {
+            return Some(message);
+        }
+
+        let sender = match message.correlation_data() {
+            Some(data) => lock(&self.pending).remove(data),
+            None => None,
+        };
+
+        match sender {
+            Some(sender) => {
+                debug!("Complete request on {}", self.response_topic);
+                if sender.send(message).is_err() {
+                    warn!("Request was dropped before reply arrived");
+                }
+                None
+            }
+            None => {
+                warn!("Drop reply with unknown correlation data");
+                None
+            }
+        }
+    }
+}
+
+/// Return value differing between process starts and between clients.
+fn nonce() -> u64 {
+    static CLIENTS: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0);
+    (nanos ^ (u64::from(std::process::id()) << 32))
+        .wrapping_add(CLIENTS.fetch_add(1, Ordering::SeqCst))
+}
+
+/// # Safety
+///
+/// Publish with MQTT v5 request/response properties, never retained. Return token
+/// tracking delivery of the publish.
+pub(crate) unsafe fn publish_with_response(
+    mosq: *mut mqtt::mosquitto,
+    deliveries: &Deliveries,
+    topic: &str,
+    payload: &[u8],
+    qos: QoS,
+    response_topic: Option<&str>,
+    correlation_data: Option<&[u8]>,
+) -> Result<DeliveryToken> {
+    let tpc = CString::new(topic)?;
+    let mut properties = Properties::new();
+    if let Some(response_topic) = response_topic {
+        properties.add_string(
+            mqtt::mqtt5_property_MQTT_PROP_RESPONSE_TOPIC,
+            response_topic,
+        )?;
+    }
+    if let Some(correlation_data) = correlation_data {
+        properties.add_binary(
+            mqtt::mqtt5_property_MQTT_PROP_CORRELATION_DATA,
+            correlation_data,
+        )?;
+    }
+
+    let mut mid: ::std::os::raw::c_int = 0;
+    deliveries.begin();
+    let status = mqtt::mosquitto_publish_v5(
+        mosq,
+        &mut mid,
+        tpc.as_ptr(),
+        payload.len() as ::std::os::raw::c_int,
+        payload.as_ptr() as *const ::std::os::raw::c_void,
+        qos.as_raw(),
+        false,
+        properties.as_ptr(),
+    );
+
+    if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+        deliveries.abort();
+        return Err(Error::MosquittoPublish(status).into());
+    }
+    Ok(deliveries.track(mid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_client_id_unusable_in_topic() {
+        assert!(Requests::new("sensor+1").is_err());
+        assert!(Requests::new("sensor#").is_err());
+        assert!(Requests::new("site/sensor").is_err());
+        assert!(Requests::new("").is_err());
+        assert_eq!(
+            Requests::new("sensor").unwrap().response_topic(),
+            "sensor/rpc/response"
+        );
+    }
+
+    #[test]
+    fn completes_pending_request() {
+        let requests = Requests::new("sensor").unwrap();
+        let (correlation_data, receiver) = requests.register();
+        let reply = Message::new("sensor/rpc/response", b"pong")
+            .with_correlation_data(Some(correlation_data));
+        assert!(requests.complete(reply).is_none());
+        assert_eq!(receiver.try_recv().unwrap().payload(), b"pong");
+    }
+
+    #[test]
+    fn correlation_data_is_unique_per_instance() {
+        let first = Requests::new("sensor").unwrap().register().0;
+        let second = Requests::new("sensor").unwrap().register().0;
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn passes_on_other_messages() {
+        let requests = Requests::new("sensor").unwrap();
+        let message = Message::new("sensor/data", b"1");
+        assert!(requests.complete(message).is_some());
+    }
+}
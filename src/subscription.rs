@@ -0,0 +1,171 @@
+use mosquitto_mqtt_sys_rust as mqtt;
+
+use crate::model::{QoS, SubscribeOptions};
+use crate::sync::lock;
+use crate::{Error, Result};
+use log::{error, info};
+use std::ffi::CString;
+use std::ptr;
+use std::sync::Mutex;
+
+#[derive(Clone)]
+struct Subscription {
+    filter: String,
+    qos: QoS,
+    options: SubscribeOptions,
+    // Subscribed on the current connection, e.g. in the connect callback.
+    current: bool,
+}
+
+/// Active subscriptions of a client, replayed when broker lost the session.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    entries: Mutex<Vec<Subscription>>,
+}
+
+impl Subscriptions {
+    /// Remember subscription, replacing an earlier one with the same filter.
+    pub fn add(&self, filter: &str, qos: QoS, options: SubscribeOptions) {
+        let mut entries = lock(&self.entries);
+        entries.retain(|entry| entry.filter != filter);
+        entries.push(Subscription {
+            filter: filter.to_string(),
+            qos,
+            options,
+            current: true,
+        });
+    }
+
+    pub fn remove(&self, filter: &str) {
+        lock(&self.entries).retain(|entry| entry.filter != filter);
+    }
+
+    /// Mark all subscriptions as made on an earlier connection.
+    pub fn connected(&self) {
+        for entry in lock(&self.entries).iter_mut() {
+            entry.current = false;
+        }
+    }
+
+    /// Return subscriptions not made since connected was called.
+    fn stale(&self) -> Vec<Subscription> {
+        lock(&self.entries)
+            .iter()
+            .filter(|entry| !entry.current)
+            .cloned()
+            .collect()
+    }
+
+    /// # Safety
+    ///
+    /// Subscribe again to all remembered filters, except those subscribed since
+    /// connected was called.
+    pub unsafe fn replay(&self, mosq: *mut mqtt::mosquitto) {
+        let stale = self.stale();
+        info!("Resubscribing {} subscriptions", stale.len());
+        for entry in stale {
+            if let Err(err) = subscribe(mosq, &entry.filter, entry.qos, entry.options) {
+                error!("Could not resubscribe to {}: {}", entry.filter, err);
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// Subscribe with MQTT v5 subscription options. Return message id.
+pub(crate) unsafe fn subscribe(
+    mosq: *mut mqtt::mosquitto,
+    filter: &str,
+    qos: QoS,
+    options: SubscribeOptions,
+) -> Result<i32> {
+    let subscription_pattern = CString::new(filter)?;
+    let mut mid: ::std::os::raw::c_int = 0;
+    let status = mqtt::mosquitto_subscribe_v5(
+        mosq,
+        &mut mid,
+        subscription_pattern.as_ptr(),
+        qos.as_raw(),
+        options.as_raw(),
+        ptr::null(),
+    );
+
+    if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+        return Err(Error::MosquittoSubscribe(status).into());
+    }
+    Ok(mid)
+}
+
+/// # Safety
+///
+/// Unsubscribe from topic filter.
+pub(crate) unsafe fn unsubscribe(mosq: *mut mqtt::mosquitto, filter: &str) -> Result<()> {
+    let subscription_pattern = CString::new(filter)?;
+    let status = mqtt::mosquitto_unsubscribe(mosq, ptr::null_mut(), subscription_pattern.as_ptr());
+
+    if status != mqtt::mosq_err_t_MOSQ_ERR_SUCCESS {
+        return Err(Error::MosquittoUnsubscribe(status).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters(subscriptions: &[Subscription]) -> Vec<&str> {
+        subscriptions
+            .iter()
+            .map(|entry| entry.filter.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn replays_subscriptions_of_earlier_connection() {
+        let subscriptions = Subscriptions::default();
+        subscriptions.add("a", QoS::AtLeastOnce, SubscribeOptions::default());
+        subscriptions.add("b", QoS::AtMostOnce, SubscribeOptions::default());
+        assert!(subscriptions.stale().is_empty());
+
+        subscriptions.connected();
+        let stale = subscriptions.stale();
+        assert_eq!(filters(&stale), vec!["a", "b"]);
+        assert_eq!(stale[0].qos, QoS::AtLeastOnce);
+    }
+
+    #[test]
+    fn replay_skips_subscriptions_made_after_connect() {
+        let subscriptions = Subscriptions::default();
+        subscriptions.add("a", QoS::AtMostOnce, SubscribeOptions::default());
+        subscriptions.add("b", QoS::AtMostOnce, SubscribeOptions::default());
+        subscriptions.connected();
+
+        // E.g. subscribed again by the connect callback.
+        subscriptions.add("a", QoS::ExactlyOnce, SubscribeOptions::default());
+        subscriptions.add("c", QoS::AtMostOnce, SubscribeOptions::default());
+        assert_eq!(filters(&subscriptions.stale()), vec!["b"]);
+    }
+
+    #[test]
+    fn keeps_one_entry_per_filter() {
+        let subscriptions = Subscriptions::default();
+        subscriptions.add("a", QoS::AtMostOnce, SubscribeOptions::default());
+        subscriptions.connected();
+        subscriptions.add("a", QoS::AtLeastOnce, SubscribeOptions::default());
+        subscriptions.connected();
+
+        let stale = subscriptions.stale();
+        assert_eq!(filters(&stale), vec!["a"]);
+        assert_eq!(stale[0].qos, QoS::AtLeastOnce);
+    }
+
+    #[test]
+    fn forgets_removed_subscriptions() {
+        let subscriptions = Subscriptions::default();
+        subscriptions.add("a", QoS::AtMostOnce, SubscribeOptions::default());
+        subscriptions.remove("a");
+        subscriptions.connected();
+        assert!(subscriptions.stale().is_empty());
+    }
+}
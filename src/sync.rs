@@ -0,0 +1,8 @@
+use std::sync::{Mutex, MutexGuard};
+
+/// Lock mutex, recovering the data of a lock poisoned by a panicking callback.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
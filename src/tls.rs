@@ -0,0 +1,465 @@
+use crate::{Error, Result};
+use log::{debug, error};
+use std::ffi::CString;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Minimum TLS protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    /// TLS 1.1 or later.
+    Tlsv1_1,
+    /// TLS 1.2 or later.
+    Tlsv1_2,
+    /// TLS 1.3 only.
+    Tlsv1_3,
+}
+
+impl TlsVersion {
+    /// Return version as expected by libmosquitto.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TlsVersion::Tlsv1_1 => "tlsv1.1",
+            TlsVersion::Tlsv1_2 => "tlsv1.2",
+            TlsVersion::Tlsv1_3 => "tlsv1.3",
+        }
+    }
+}
+
+/// Broker certificate verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifyMode {
+    /// Verify broker certificate against the trusted CAs.
+    #[default]
+    Peer,
+    /// Do not verify broker certificate. The connection is not protected against
+    /// man-in-the-middle attacks.
+    None,
+}
+
+impl VerifyMode {
+    /// Return cert_reqs value as expected by libmosquitto.
+    pub fn as_raw(self) -> i32 {
+        match self {
+            VerifyMode::Peer => 1,
+            VerifyMode::None => 0,
+        }
+    }
+}
+
+/// TLS configuration for a broker connection.
+///
+/// Either a CA file, a CA directory or the OS trust store must be given. A client
+/// certificate and key enable mutual TLS; an encrypted key can be unlocked by a
+/// password callback. Certificates and keys may also be given as PEM strings.
+///
+/// libmosquitto only loads a client certificate together with a CA file or CA
+/// directory. To use mutual TLS with the OS trust store, give a CA as well and
+/// enable `use_os_certs`.
+#[derive(Default)]
+pub struct TlsConfig {
+    ca_file: Option<PathBuf>,
+    ca_path: Option<PathBuf>,
+    cert_file: Option<PathBuf>,
+    key_file: Option<PathBuf>,
+    ca_pem: Option<String>,
+    cert_pem: Option<String>,
+    key_pem: Option<String>,
+    use_os_certs: bool,
+    alpn: Option<String>,
+    key_password: Option<Box<dyn Fn() -> String + Send>>,
+    version: Option<TlsVersion>,
+    ciphers: Option<String>,
+    verify_mode: VerifyMode,
+    insecure: bool,
+}
+
+impl TlsConfig {
+    /// Trust certificates from a PEM encoded CA file.
+    pub fn with_ca_file<P: AsRef<Path>>(ca_file: P) -> Self {
+        TlsConfig {
+            ca_file: Some(ca_file.as_ref().to_path_buf()),
+            ..Default::default()
+        }
+    }
+
+    /// Trust certificates from a directory prepared with `openssl rehash`.
+    pub fn with_ca_path<P: AsRef<Path>>(ca_path: P) -> Self {
+        TlsConfig {
+            ca_path: Some(ca_path.as_ref().to_path_buf()),
+            ..Default::default()
+        }
+    }
+
+    /// Trust certificates from a PEM encoded string.
+    pub fn with_ca_pem(ca_pem: &str) -> Self {
+        TlsConfig {
+            ca_pem: Some(ca_pem.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Trust certificates from the default OS trust store.
+    /// Cannot be combined with a client certificate unless a CA is given as well.
+    pub fn with_os_certs() -> Self {
+        TlsConfig {
+            use_os_certs: true,
+            ..Default::default()
+        }
+    }
+
+    /// Trust certificates from the default OS trust store in addition to the configured CAs.
+    pub fn use_os_certs(mut self, use_os_certs: bool) -> Self {
+        self.use_os_certs = use_os_certs;
+        self
+    }
+
+    /// Authenticate with a client certificate and its private key.
+    /// Requires a CA file or CA directory.
+    pub fn client_certificate<P: AsRef<Path>, K: AsRef<Path>>(
+        mut self,
+        cert_file: P,
+        key_file: K,
+    ) -> Self {
+        self.cert_file = Some(cert_file.as_ref().to_path_buf());
+        self.key_file = Some(key_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Authenticate with a PEM encoded client certificate and private key.
+    pub fn client_certificate_pem(mut self, cert_pem: &str, key_pem: &str) -> Self {
+        self.cert_pem = Some(cert_pem.to_string());
+        self.key_pem = Some(key_pem.to_string());
+        self
+    }
+
+    /// Sets callback returning the password of an encrypted private key.
+    pub fn key_password_callback<C>(mut self, callback: C) -> Self
+    where
+        C: Fn() -> String + Send,
+        C: 'static,
+    {
+        self.key_password = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets minimum TLS version.
+    pub fn version(mut self, version: TlsVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Restrict cipher suites, in OpenSSL cipher list format.
+    pub fn ciphers(mut self, ciphers: &str) -> Self {
+        self.ciphers = Some(ciphers.to_string());
+        self
+    }
+
+    /// Sets broker certificate verification.
+    pub fn verify_mode(mut self, verify_mode: VerifyMode) -> Self {
+        self.verify_mode = verify_mode;
+        self
+    }
+
+    /// Disable verification of the broker hostname against its certificate.
+    /// Only meant for testing environments.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Sets ALPN protocol announced during the TLS handshake, e.g. "mqtt" for
+    /// brokers sharing port 443 behind a load balancer.
+    ///
+    /// libmosquitto sends the host given to connect as SNI server name and verifies
+    /// the broker certificate against it, so connect using the SNI hostname.
+    pub fn alpn(mut self, protocol: &str) -> Self {
+        self.alpn = Some(protocol.to_string());
+        self
+    }
+
+    /// Write PEM strings to files only readable by the current user, as libmosquitto
+    /// loads certificates from disk. The files must be kept until the client is dropped.
+    pub(crate) fn write_pem_files(&mut self) -> Result<Vec<SecureFile>> {
+        let mut files = Vec::new();
+        if let Some(ca_pem) = self.ca_pem.take() {
+            let file = SecureFile::create(&ca_pem)?;
+            self.ca_file = Some(file.path().to_path_buf());
+            files.push(file);
+        }
+        if let Some(cert_pem) = self.cert_pem.take() {
+            let file = SecureFile::create(&cert_pem)?;
+            self.cert_file = Some(file.path().to_path_buf());
+            files.push(file);
+        }
+        if let Some(key_pem) = self.key_pem.take() {
+            let file = SecureFile::create(&key_pem)?;
+            self.key_file = Some(file.path().to_path_buf());
+            files.push(file);
+        }
+        Ok(files)
+    }
+
+    /// Reject configurations libmosquitto cannot load: a client certificate is only
+    /// loaded by mosquitto_tls_set, which requires a CA file or CA directory.
+    pub(crate) fn check(&self) -> Result<()> {
+        let has_ca = self.ca_file.is_some() || self.ca_path.is_some() || self.ca_pem.is_some();
+        let has_cert = self.cert_file.is_some()
+            || self.key_file.is_some()
+            || self.cert_pem.is_some()
+            || self.key_pem.is_some();
+        if !has_ca && self.use_os_certs && has_cert {
+            return Err(Error::ClientCertificateWithoutCa.into());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn uses_os_certs(&self) -> bool {
+        self.use_os_certs
+    }
+
+    pub(crate) fn alpn_protocol(&self) -> Option<&str> {
+        self.alpn.as_deref()
+    }
+
+    pub(crate) fn has_options(&self) -> bool {
+        self.version.is_some() || self.ciphers.is_some() || self.verify_mode != VerifyMode::Peer
+    }
+
+    pub(crate) fn tls_version(&self) -> Result<Option<CString>> {
+        match self.version {
+            Some(version) => Ok(Some(CString::new(version.as_str())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn cipher_list(&self) -> Result<Option<CString>> {
+        match &self.ciphers {
+            Some(ciphers) => Ok(Some(CString::new(ciphers.as_str())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn get_verify_mode(&self) -> VerifyMode {
+        self.verify_mode
+    }
+
+    pub(crate) fn is_insecure(&self) -> bool {
+        self.insecure
+    }
+
+    pub(crate) fn ca_file(&self) -> Result<Option<CString>> {
+        optional_path(&self.ca_file)
+    }
+
+    pub(crate) fn ca_path(&self) -> Result<Option<CString>> {
+        optional_path(&self.ca_path)
+    }
+
+    pub(crate) fn cert_file(&self) -> Result<Option<CString>> {
+        optional_path(&self.cert_file)
+    }
+
+    pub(crate) fn key_file(&self) -> Result<Option<CString>> {
+        optional_path(&self.key_file)
+    }
+
+    pub(crate) fn take_key_password(&mut self) -> Option<Box<dyn Fn() -> String + Send>> {
+        self.key_password.take()
+    }
+}
+
+/// TLS-PSK configuration, an alternative to certificate based TLS.
+#[derive(Clone)]
+pub struct PskConfig {
+    psk: String,
+    identity: String,
+    ciphers: Option<String>,
+}
+
+impl std::fmt::Debug for PskConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PskConfig")
+            .field("psk", &"<redacted>")
+            .field("identity", &self.identity)
+            .field("ciphers", &self.ciphers)
+            .finish()
+    }
+}
+
+impl PskConfig {
+    /// Create PSK configuration from a hex encoded key and the client identity.
+    pub fn new(psk: &str, identity: &str) -> Result<Self> {
+        if psk.is_empty() || psk.len() % 2 == 1 || !psk.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::InvalidPsk.into());
+        }
+
+        Ok(PskConfig {
+            psk: psk.to_string(),
+            identity: identity.to_string(),
+            ciphers: None,
+        })
+    }
+
+    /// Restrict PSK cipher suites, in OpenSSL cipher list format.
+    pub fn ciphers(mut self, ciphers: &str) -> Self {
+        self.ciphers = Some(ciphers.to_string());
+        self
+    }
+
+    pub(crate) fn psk(&self) -> Result<CString> {
+        Ok(CString::new(self.psk.as_str())?)
+    }
+
+    pub(crate) fn identity(&self) -> Result<CString> {
+        Ok(CString::new(self.identity.as_str())?)
+    }
+
+    pub(crate) fn cipher_list(&self) -> Result<Option<CString>> {
+        match &self.ciphers {
+            Some(ciphers) => Ok(Some(CString::new(ciphers.as_str())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Temporary file holding PEM data in a directory only accessible by the current
+/// user. Both are removed on drop.
+pub(crate) struct SecureFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Drop for SecureFile {
+    fn drop(&mut self) {
+        debug!("Removing {}", self.path.display());
+        if let Err(err) = fs::remove_file(&self.path) {
+            error!("Could not remove {}: {}", self.path.display(), err);
+        }
+        if let Err(err) = fs::remove_dir(&self.dir) {
+            error!("Could not remove {}: {}", self.dir.display(), err);
+        }
+    }
+}
+
+impl SecureFile {
+    /// Fails where owner-only permissions are not supported, rather than writing
+    /// keys readable by other users.
+    #[cfg(unix)]
+    pub fn create(contents: &str) -> Result<Self> {
+        use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        let name = format!(
+            "mosquitto-mqtt-{}-{}-{:x}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst),
+            nanos
+        );
+        let dir = std::env::temp_dir().join(name);
+        // Fails if the path exists, so a directory or link planted there is not used.
+        DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .map_err(Error::Io)?;
+
+        let path = dir.join("tls.pem");
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(err) => {
+                let _ = fs::remove_dir(&dir);
+                return Err(Error::Io(err).into());
+            }
+        };
+        let secure_file = SecureFile { dir, path };
+        file.write_all(contents.as_bytes()).map_err(Error::Io)?;
+        file.sync_all().map_err(Error::Io)?;
+        Ok(secure_file)
+    }
+
+    #[cfg(not(unix))]
+    pub fn create(_contents: &str) -> Result<Self> {
+        Err(Error::InsecurePemFile.into())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Convert path into a C string, failing on paths which are not valid UTF-8.
+pub(crate) fn path_to_cstring(path: &Path) -> Result<CString> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?;
+    Ok(CString::new(path_str)?)
+}
+
+fn optional_path(path: &Option<PathBuf>) -> Result<Option<CString>> {
+    match path {
+        Some(path) => Ok(Some(path_to_cstring(path)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_hex_psk() {
+        assert!(PskConfig::new("00ff", "client").is_ok());
+        assert!(PskConfig::new("DEADbeef", "client").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_psk() {
+        assert!(PskConfig::new("", "client").is_err());
+        assert!(PskConfig::new("abc", "client").is_err());
+        assert!(PskConfig::new("0x12", "client").is_err());
+        assert!(PskConfig::new("zz", "client").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_pem_to_private_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = SecureFile::create("pem").unwrap();
+        let dir = file.path().parent().unwrap().to_path_buf();
+        let dir_mode = fs::metadata(&dir).unwrap().permissions().mode();
+        let file_mode = fs::metadata(file.path()).unwrap().permissions().mode();
+        assert_eq!(dir_mode & 0o777, 0o700);
+        assert_eq!(file_mode & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "pem");
+
+        drop(file);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn rejects_client_certificate_without_ca() {
+        let config = TlsConfig::with_os_certs().client_certificate_pem("cert", "key");
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn debug_redacts_psk() {
+        let config = PskConfig::new("deadbeef", "client").unwrap();
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("deadbeef"));
+        assert!(debug.contains("client"));
+    }
+}